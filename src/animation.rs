@@ -0,0 +1,339 @@
+// Keyframed zoom animations rendered by the headless path.
+//
+// A script holds global settings followed by keyframes, one setting per
+// line and '#' starting a comment, like palette and bookmark files:
+//
+//     fps 30
+//     keyframe 0
+//     center -0.5 0
+//     zoom 1
+//     iterations 500
+//     keyframe 12
+//     center -0.7436438870371587047521915061147 0.1318259042053119704931320563851
+//     zoom 1e9
+//     iterations 5000
+//     offset 0.5
+//     easing ease-in-out
+//
+// `keyframe` takes the time in seconds, settings after it belong to that
+// keyframe and carry over from the previous one when left out. `easing`
+// shapes the segment that starts at its keyframe. Between keyframes the
+// zoom changes exponentially, the center moves so it reaches the next one
+// as the view closes in on it, iterations and palette offset follow
+// linearly.
+
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::backend::{self, RenderParams};
+use crate::cli::Options;
+use crate::dd::Dd;
+use crate::headless;
+use crate::image::{self, Y4mWriter};
+use crate::palette::{setting_lines, Palette};
+use crate::viewport::Viewport;
+
+const DEFAULT_FPS: u32 = 30;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    pub(crate) fn from_name(name: &str) -> Result<Easing, String> {
+        match name {
+            "linear" => Ok(Easing::Linear),
+            "ease-in" => Ok(Easing::EaseIn),
+            "ease-out" => Ok(Easing::EaseOut),
+            "ease-in-out" | "smooth" => Ok(Easing::EaseInOut),
+            _ => Err(format!("unknown easing '{}', expected linear, ease-in, ease-out or ease-in-out", name)),
+        }
+    }
+
+    // maps the time fraction of a segment, 0 and 1 stay in place
+    fn apply(self, t: f64) -> f64 {
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Keyframe {
+    // seconds from the start
+    time: f64,
+    center: (Dd, Dd),
+    // relative to the overview like --zoom
+    zoom: f64,
+    max_iter: i32,
+    // added to the palette offset
+    offset: f32,
+    // of the segment starting here
+    easing: Easing,
+}
+
+pub(crate) struct Script {
+    fps: u32,
+    keyframes: Vec<Keyframe>,
+}
+
+impl Script {
+    // `max_iter` is used until a keyframe sets iterations
+    pub(crate) fn load(path: &str, max_iter: i32) -> Result<Script, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Script::parse(&text, max_iter).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub(crate) fn parse(text: &str, max_iter: i32) -> Result<Script, String> {
+        let mut fps = DEFAULT_FPS;
+        let mut keyframes: Vec<Keyframe> = Vec::new();
+        // whether the first keyframe set a center and a zoom
+        let mut placed = (false, false);
+        for line in setting_lines(text) {
+            if line.name() == "fps" {
+                fps = line.value(1)?;
+                if fps == 0 {
+                    return Err(line.err("fps must be positive"));
+                }
+                continue;
+            }
+            if line.name() == "keyframe" {
+                let time = line.value(1)?;
+                let key = match keyframes.last() {
+                    Some(last) if time <= last.time => return Err(line.err("keyframe times must increase")),
+                    Some(last) => Keyframe { time, ..*last },
+                    None => Keyframe {
+                        time,
+                        center: (Dd::default(), Dd::default()),
+                        zoom: 1.0,
+                        max_iter,
+                        offset: 0.0,
+                        easing: Easing::Linear,
+                    },
+                };
+                keyframes.push(key);
+                continue;
+            }
+            let key = keyframes.last_mut().ok_or_else(|| line.err(&format!("'{}' before the first keyframe", line.name())))?;
+            match line.name() {
+                "center" => {
+                    key.center = (line.value(1)?, line.value(2)?);
+                    placed.0 |= keyframes.len() == 1;
+                }
+                "zoom" => {
+                    key.zoom = line.value(1)?;
+                    if key.zoom <= 0.0 {
+                        return Err(line.err("zoom must be positive"));
+                    }
+                    placed.1 |= keyframes.len() == 1;
+                }
+                "iterations" => {
+                    key.max_iter = line.value(1)?;
+                    if key.max_iter <= 0 {
+                        return Err(line.err("iterations must be positive"));
+                    }
+                }
+                "offset" => key.offset = line.value(1)?,
+                "easing" => key.easing = Easing::from_name(line.word(1)?).map_err(|e| line.err(&e))?,
+                other => return Err(line.err(&format!("unknown setting '{}'", other))),
+            }
+        }
+        if keyframes.is_empty() {
+            return Err("the script has no keyframes".to_string());
+        }
+        if placed != (true, true) {
+            return Err("the first keyframe needs a center and a zoom".to_string());
+        }
+        Ok(Script { fps, keyframes })
+    }
+
+    // frames from the first keyframe up to and including the last
+    pub(crate) fn frames(&self) -> u32 {
+        let duration = self.keyframes.last().unwrap().time - self.keyframes[0].time;
+        (duration * self.fps as f64).floor() as u32 + 1
+    }
+
+    // the interpolated keyframe `time` seconds after the first one
+    fn at(&self, time: f64) -> Keyframe {
+        let time = self.keyframes[0].time + time;
+        let Some(k) = self.keyframes.windows(2).position(|pair| time < pair[1].time) else {
+            return *self.keyframes.last().unwrap();
+        };
+        let (a, b) = (&self.keyframes[k], &self.keyframes[k + 1]);
+        let u = a.easing.apply((time - a.time) / (b.time - a.time));
+
+        // exponential zoom, the pixel spacing s shrinks geometrically
+        let zoom = a.zoom * (b.zoom / a.zoom).powf(u);
+        // the center moves in step with the pixel spacing, most of the way
+        // while the view is still wide, so the target does not race across
+        // the screen once it is magnified
+        let (sa, sb, s) = (1.0 / a.zoom, 1.0 / b.zoom, 1.0 / zoom);
+        let w = if ((sa - sb) / sa).abs() > 1e-12 { (sa - s) / (sa - sb) } else { u };
+        let center = (a.center.0 + (b.center.0 - a.center.0) * w, a.center.1 + (b.center.1 - a.center.1) * w);
+
+        Keyframe {
+            time,
+            center,
+            zoom,
+            max_iter: (a.max_iter as f64 + (b.max_iter - a.max_iter) as f64 * u).round() as i32,
+            offset: a.offset + (b.offset - a.offset) * u as f32,
+            easing: a.easing,
+        }
+    }
+}
+
+// `frames/zoom.png` becomes `frames/zoom-00042.png`
+fn frame_path(output: &str, frame: u32, digits: usize) -> String {
+    let path = Path::new(output);
+    let stem = path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{}-{:0width$}.{}", stem, frame, ext.to_string_lossy(), width = digits),
+        None => format!("{}-{:0width$}", stem, frame, width = digits),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+// Renders the script given with --script to --output: a numbered image
+// sequence, or one Y4M stream when the output ends in .y4m.
+pub(crate) fn run(options: &Options) -> Result<(), String> {
+    let path = options.script.as_deref().ok_or("animate needs a keyframe script, see --script")?;
+    let script = Script::load(path, options.max_iter)?;
+    let (mut renderer, _) = backend::open_with_fallback(options.backend_or("cpu"), &options.device)?;
+    let palette = Palette::by_name_or_file(&options.palette)?;
+    let params = options.params();
+    let overview = Viewport::overview_of(options.julia.is_some(), options.width, options.height);
+
+    let frames = script.frames();
+    let mut video = if options.output.to_lowercase().ends_with(".y4m") {
+        Some(Y4mWriter::create(&options.output, options.width, options.height, script.fps)?)
+    } else {
+        image::Format::for_path(&options.output)?;
+        if let Some(dir) = Path::new(&options.output).parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        }
+        None
+    };
+    let digits = (frames - 1).to_string().len().max(5);
+
+    let timer = Instant::now();
+    for n in 0..frames {
+        let key = script.at(n as f64 / script.fps as f64);
+        let viewport = Viewport { center_x: key.center.0, center_y: key.center.1, scale: overview.scale / key.zoom,
+            width: options.width, height: options.height };
+        let params = RenderParams { max_iter: key.max_iter, ..params };
+        let palette = Palette { offset: palette.offset + key.offset, ..palette.clone() };
+        match video.as_mut() {
+            Some(video) => {
+                let mut pixels = Vec::with_capacity(viewport.len());
+                headless::render_bands(&viewport, &params, &options.sampling, renderer.as_mut(), &palette,
+                    &mut |rows| {
+                        pixels.extend_from_slice(rows);
+                        Ok(())
+                    })?;
+                video.write_frame(&pixels)?;
+                println!("frame {}/{}", n + 1, frames);
+            }
+            None => headless::render(&viewport, &params, &options.sampling, renderer.as_mut(), &palette,
+                &frame_path(&options.output, n, digits))?,
+        }
+    }
+    if let Some(video) = video {
+        video.finish()?;
+        println!("wrote {}", options.output);
+    }
+    println!("{} frames in {:.1} s", frames, timer.elapsed().as_secs_f64());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // zooms in on (1, -1) over two seconds and back out over the next two
+    const SCRIPT: &str = "fps 10\n\
+        keyframe 1\ncenter 0 0\nzoom 1\niterations 100\n\
+        keyframe 3\ncenter 1 -1\nzoom 1e6\niterations 300\noffset 0.5\n\
+        keyframe 5\ncenter 0 0\nzoom 1\n";
+
+    #[test]
+    fn keyframes_are_hit_exactly() {
+        let script = Script::parse(SCRIPT, 1000).unwrap();
+        assert_eq!(script.frames(), 41);
+        for (time, key) in [(0.0, &script.keyframes[0]), (2.0, &script.keyframes[1]), (4.0, &script.keyframes[2])] {
+            let at = script.at(time);
+            assert_eq!(at.center, key.center);
+            assert_eq!(at.zoom, key.zoom);
+            assert_eq!(at.max_iter, key.max_iter);
+            assert_eq!(at.offset, key.offset);
+        }
+        // settings left out carry over
+        assert_eq!(script.keyframes[2].max_iter, 300);
+        assert_eq!(script.keyframes[2].offset, 0.5);
+    }
+
+    #[test]
+    fn zoom_is_geometric() {
+        let script = Script::parse(SCRIPT, 1000).unwrap();
+        assert!((script.at(1.0).zoom / 1e3 - 1.0).abs() < 1e-12);
+        assert!((script.at(3.0).zoom / 1e3 - 1.0).abs() < 1e-12);
+        assert_eq!(script.at(1.0).max_iter, 200);
+    }
+
+    #[test]
+    fn center_moves_monotonically_between_keyframes() {
+        let script = Script::parse(SCRIPT, 1000).unwrap();
+        let mut previous = 0.0;
+        for n in 0..=40 {
+            // the weight of the next keyframe's center, rising then falling
+            let w = script.at(n as f64 / 10.0).center.0.to_f64();
+            assert!((0.0..=1.0).contains(&w), "w = {} at frame {}", w, n);
+            if n <= 20 {
+                assert!(w >= previous, "w falls to {} at frame {} while zooming in", w, n);
+            } else {
+                assert!(w <= previous, "w rises to {} at frame {} while zooming out", w, n);
+            }
+            previous = w;
+        }
+    }
+
+    #[test]
+    fn easing_keeps_the_ends() {
+        for easing in [Easing::Linear, Easing::EaseIn, Easing::EaseOut, Easing::EaseInOut] {
+            assert_eq!(easing.apply(0.0), 0.0);
+            assert_eq!(easing.apply(1.0), 1.0);
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        let cases = [
+            ("fps 24", "the script has no keyframes"),
+            ("keyframe 0\nzoom 2", "the first keyframe needs a center and a zoom"),
+            ("keyframe 0\ncenter 0 0", "the first keyframe needs a center and a zoom"),
+            ("keyframe 0\nzoom 2\nkeyframe 1\ncenter 0 0", "the first keyframe needs a center and a zoom"),
+            ("center 0 0\nkeyframe 0", "line 1: 'center' before the first keyframe"),
+            ("keyframe 1\ncenter 0 0\nzoom 1\nkeyframe 1", "line 4: keyframe times must increase"),
+            ("keyframe 0\nzoom -1", "line 2: zoom must be positive"),
+            ("fps 0", "line 1: fps must be positive"),
+            ("keyframe 0\neasing bounce", "line 2: unknown easing 'bounce', expected linear, ease-in, ease-out or ease-in-out"),
+        ];
+        for (text, err) in cases {
+            assert_eq!(Script::parse(text, 1000).err().unwrap(), err, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn frame_paths() {
+        assert_eq!(frame_path("frames/zoom.png", 42, 5), "frames/zoom-00042.png");
+        assert_eq!(frame_path("zoom", 7, 3), "zoom-007");
+        assert_eq!(frame_path("zoom.ppm", 123456, 5), "zoom-123456.ppm");
+    }
+}
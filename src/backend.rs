@@ -0,0 +1,309 @@
+use std::time::Duration;
+
+use crate::compute::OclRenderer;
+use crate::cpu::CpuRenderer;
+use crate::device::{DeviceFilter, DeviceType};
+use crate::ocl3::Ocl3Renderer;
+use crate::perturbation::PerturbationRenderer;
+use crate::viewport::Viewport;
+
+pub(crate) const BACKEND_NAMES: [&str; 5] = ["ocl", "opencl3", "cpu", "cpu64", "perturbation"];
+// the backends that need an OpenCL device
+pub(crate) const OPENCL_BACKENDS: [&str; 3] = ["ocl", "opencl3", "perturbation"];
+
+// pixels of one band of a cancellable render, a dropped view waits for at
+// most one band to finish
+const CANCEL_BAND_PIXELS: usize = 1 << 18;
+
+// f32 keeps 24 bits of mantissa, below this many ulps per pixel the image
+// turns into blocks
+const SINGLE_MIN_ULPS_PER_PIXEL: f64 = 32.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Precision {
+    Auto,
+    Single,
+    Double,
+}
+
+impl Precision {
+    pub(crate) fn from_name(name: &str) -> Result<Precision, String> {
+        match name {
+            "auto" => Ok(Precision::Auto),
+            "single" | "f32" => Ok(Precision::Single),
+            "double" | "f64" => Ok(Precision::Double),
+            _ => Err(format!("unknown precision '{}', expected auto, single or double", name)),
+        }
+    }
+
+    // picks single or double precision for the pixel spacing of the viewport
+    pub(crate) fn resolve(self, viewport: &Viewport) -> Precision {
+        match self {
+            Precision::Auto => {
+                let magnitude = viewport.center_x.to_f64().abs().max(viewport.center_y.to_f64().abs()).max(1.0);
+                let ulp = f32::EPSILON as f64 * magnitude;
+                if viewport.scale < ulp * SINGLE_MIN_ULPS_PER_PIXEL {
+                    Precision::Double
+                } else {
+                    Precision::Single
+                }
+            }
+            p => p,
+        }
+    }
+}
+
+pub(crate) const FORMULA_NAMES: [&str; 5] = ["mandelbrot", "multibrot", "burning-ship", "tricorn", "celtic"];
+
+// The iterated function z -> f(z) + c. The OpenCL kernels get the position
+// in FORMULA_NAMES as `formula` and the multibrot exponent as `power`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Formula {
+    Mandelbrot,
+    // z^d + c, d may be any real number
+    Multibrot(f64),
+    // (|Re z| + i|Im z|)^2 + c
+    BurningShip,
+    // conj(z)^2 + c, also called Mandelbar
+    Tricorn,
+    // |Re z^2| + i Im z^2 + c
+    Celtic,
+}
+
+impl Formula {
+    // `power` is only used by multibrot
+    pub(crate) fn from_name(name: &str, power: f64) -> Result<Formula, String> {
+        match name {
+            "mandelbrot" => Ok(Formula::Mandelbrot),
+            "multibrot" => Ok(Formula::Multibrot(power)),
+            "burning-ship" => Ok(Formula::BurningShip),
+            "tricorn" | "mandelbar" => Ok(Formula::Tricorn),
+            "celtic" => Ok(Formula::Celtic),
+            _ => Err(format!("unknown formula '{}', expected one of {:?}", name, FORMULA_NAMES)),
+        }
+    }
+
+    // the name from_name takes
+    pub(crate) fn name(self) -> &'static str {
+        FORMULA_NAMES[self.kernel_id() as usize]
+    }
+
+    pub(crate) fn kernel_id(self) -> i32 {
+        match self {
+            Formula::Mandelbrot => 0,
+            Formula::Multibrot(_) => 1,
+            Formula::BurningShip => 2,
+            Formula::Tricorn => 3,
+            Formula::Celtic => 4,
+        }
+    }
+
+    pub(crate) fn power(self) -> f64 {
+        match self {
+            Formula::Multibrot(power) => power,
+            _ => 2.0,
+        }
+    }
+
+    // cycles through the formulas, multibrot is entered with `power`
+    pub(crate) fn next(self, power: f64) -> Formula {
+        match self {
+            Formula::Mandelbrot => Formula::Multibrot(power),
+            Formula::Multibrot(_) => Formula::BurningShip,
+            Formula::BurningShip => Formula::Tricorn,
+            Formula::Tricorn => Formula::Celtic,
+            Formula::Celtic => Formula::Mandelbrot,
+        }
+    }
+}
+
+// squared escape radius of the plain escape time algorithm
+pub(crate) const BAILOUT: f64 = 4.0;
+// squared escape radius used for smooth counts, a large radius keeps the
+// fractional part free of visible seams
+pub(crate) const SMOOTH_BAILOUT: f64 = 65536.0;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct RenderParams {
+    pub(crate) max_iter: i32,
+    pub(crate) precision: Precision,
+    // also produce fractional escape counts
+    pub(crate) smooth: bool,
+    // render the Julia set of this c instead of the Mandelbrot set
+    pub(crate) julia: Option<(f64, f64)>,
+    pub(crate) formula: Formula,
+    // skip iterating points in the main cardioid and period-2 bulb, only
+    // used for the plain Mandelbrot set
+    pub(crate) interior_check: bool,
+    // stop early on orbits that cycle and report their period
+    pub(crate) periodicity: bool,
+    // also produce the cycle length of interior points, see
+    // IterationBuffer::period
+    pub(crate) periods: bool,
+    // move every sample by a pseudo-random offset of up to half a pixel,
+    // see supersample::jitter
+    pub(crate) jitter: bool,
+}
+
+impl RenderParams {
+    pub(crate) fn bailout(&self) -> f64 {
+        if self.smooth {
+            SMOOTH_BAILOUT
+        } else {
+            BAILOUT
+        }
+    }
+}
+
+// Escape counts of one frame, stored row by row like the viewport pixels.
+#[derive(Clone, Debug)]
+pub(crate) struct IterationBuffer {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) data: Vec<i32>,
+    // fractional escape counts, present when RenderParams::smooth is set
+    pub(crate) smooth: Option<Vec<f32>>,
+    // cycle length of interior points, 0 where none was detected, present
+    // when RenderParams::periods is set
+    pub(crate) period: Option<Vec<i32>>,
+}
+
+impl IterationBuffer {
+    pub(crate) fn new(width: u32, height: u32) -> IterationBuffer {
+        IterationBuffer { width, height, data: vec![0; width as usize * height as usize], smooth: None, period: None }
+    }
+
+    // buffer with room for the outputs asked for in `params`
+    pub(crate) fn for_params(width: u32, height: u32, params: &RenderParams) -> IterationBuffer {
+        let mut buffer = IterationBuffer::new(width, height);
+        if params.smooth {
+            buffer.smooth = Some(vec![0.0; buffer.data.len()]);
+        }
+        if params.periods {
+            buffer.period = Some(vec![0; buffer.data.len()]);
+        }
+        buffer
+    }
+
+    pub(crate) fn get(&self, i: u32, j: u32) -> i32 {
+        self.data[j as usize * self.width as usize + i as usize]
+    }
+
+    // copies `band`, rows j.. of the same view, into this buffer
+    pub(crate) fn copy_rows(&mut self, j: u32, band: &IterationBuffer) {
+        let start = j as usize * self.width as usize;
+        let end = start + band.data.len();
+        self.data[start..end].copy_from_slice(&band.data);
+        if let (Some(smooth), Some(known)) = (self.smooth.as_mut(), band.smooth.as_ref()) {
+            smooth[start..end].copy_from_slice(known);
+        }
+        if let (Some(periods), Some(known)) = (self.period.as_mut(), band.period.as_ref()) {
+            periods[start..end].copy_from_slice(known);
+        }
+    }
+
+    // smooth count if available, the integer count otherwise
+    pub(crate) fn value(&self, i: u32, j: u32) -> f32 {
+        let idx = j as usize * self.width as usize + i as usize;
+        match &self.smooth {
+            Some(smooth) => smooth[idx],
+            None => self.data[idx] as f32,
+        }
+    }
+}
+
+// Time spent in the phases of one render call.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Timings {
+    // host work before anything is queued: reference orbits, buffer
+    // allocation, kernel arguments
+    pub(crate) setup: Duration,
+    // host to device copies
+    pub(crate) upload: Duration,
+    pub(crate) kernel: Duration,
+    // device to host copies
+    pub(crate) readback: Duration,
+}
+
+// Send so the viewer can render on a worker thread
+pub(crate) trait Renderer: Send {
+    fn name(&self) -> &'static str;
+
+    fn render(&mut self, viewport: &Viewport, params: &RenderParams) -> Result<IterationBuffer, String>;
+
+    // Renders like `render`, but gives up with Ok(None) as soon as
+    // `cancelled` returns true. The default renders bands of rows one after
+    // another and checks between them, backends that can check more often
+    // override this. `coarse` may hold the same view at half the resolution
+    // (Viewport::coarse(2)), whose pixel (i, j) is pixel (2i, 2j) here,
+    // backends for which skipping those pixels pays off use it.
+    fn render_cancellable(&mut self, viewport: &Viewport, params: &RenderParams, coarse: Option<&IterationBuffer>,
+                          cancelled: &dyn Fn() -> bool) -> Result<Option<IterationBuffer>, String> {
+        let _ = coarse;
+        let max_pixels = self.max_pixels().unwrap_or(CANCEL_BAND_PIXELS).min(CANCEL_BAND_PIXELS);
+        let band = (max_pixels / viewport.width as usize).clamp(1, viewport.height as usize) as u32;
+        let mut buffer = IterationBuffer::for_params(viewport.width, viewport.height, params);
+        let mut j = 0;
+        while j < viewport.height {
+            if cancelled() {
+                return Ok(None);
+            }
+            let rows = band.min(viewport.height - j);
+            buffer.copy_rows(j, &self.render(&viewport.tile(0, j, viewport.width, rows), params)?);
+            j += rows;
+        }
+        Ok(Some(buffer))
+    }
+
+    // largest frame in pixels one render call can handle, None when only
+    // host memory limits it
+    fn max_pixels(&self) -> Option<usize> {
+        None
+    }
+
+    // phases of the last render, None for backends that do all their work
+    // on the host
+    fn timings(&self) -> Option<Timings> {
+        None
+    }
+}
+
+// `filter` picks the OpenCL device, the CPU backends ignore it
+pub(crate) fn by_name(name: &str, filter: &DeviceFilter) -> Result<Box<dyn Renderer>, String> {
+    match name {
+        "ocl" => Ok(Box::new(OclRenderer::new(filter)?)),
+        "opencl3" => Ok(Box::new(Ocl3Renderer::new(filter)?)),
+        "cpu" => Ok(Box::new(CpuRenderer::new(Precision::Auto))),
+        "cpu64" => Ok(Box::new(CpuRenderer::new(Precision::Double))),
+        "perturbation" => Ok(Box::new(PerturbationRenderer::new(filter)?)),
+        _ => Err(format!("unknown backend '{}', expected one of {:?}", name, BACKEND_NAMES)),
+    }
+}
+
+// Opens the named backend like `by_name`, but when no device was asked for
+// explicitly and the OpenCL backend cannot start, falls back to an OpenCL CPU
+// device and then to the native CPU renderer, printing why to stderr.
+// Returns the device filter that worked, for later backend switches.
+pub(crate) fn open_with_fallback(name: &str, filter: &DeviceFilter) -> Result<(Box<dyn Renderer>, DeviceFilter), String> {
+    let err = match by_name(name, filter) {
+        Ok(renderer) => return Ok((renderer, filter.clone())),
+        Err(err) => err,
+    };
+    if !OPENCL_BACKENDS.contains(&name) || filter.is_explicit() {
+        return Err(err);
+    }
+    eprintln!("warning: {} backend unavailable: {}", name, err);
+
+    if filter.device_type() == DeviceType::Gpu {
+        let cpu_filter = DeviceFilter { kind: Some(DeviceType::Cpu), ..filter.clone() };
+        match by_name(name, &cpu_filter) {
+            Ok(renderer) => {
+                eprintln!("warning: using an OpenCL CPU device instead");
+                return Ok((renderer, cpu_filter));
+            }
+            Err(err) => eprintln!("warning: no OpenCL CPU device either: {}", err),
+        }
+    }
+    eprintln!("warning: falling back to the native cpu backend");
+    Ok((by_name("cpu", filter)?, filter.clone()))
+}
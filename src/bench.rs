@@ -0,0 +1,178 @@
+// Backend benchmark over a fixed set of scenes.
+//
+// Every backend renders every scene once to warm up (kernel builds, buffer
+// allocation) and then `runs` more times. Frame times are host wall clock,
+// the phase breakdown comes from Renderer::timings, for backends without it
+// the whole frame counts as kernel time.
+
+use std::fs;
+use std::time::{Duration, Instant};
+
+use crate::backend::{self, Timings, BACKEND_NAMES};
+use crate::cli::Options;
+use crate::viewport::Viewport;
+
+struct Scene {
+    name: &'static str,
+    center: (f64, f64),
+    zoom: f64,
+}
+
+const SCENES: [Scene; 3] = [
+    // the whole set, a mix of fast escaping and interior pixels
+    Scene { name: "full", center: (-0.5, 0.0), zoom: 1.0 },
+    // seahorse valley, nearly every pixel is close to the boundary
+    Scene { name: "boundary", center: (-0.743643887037151, 0.131825904205330), zoom: 500.0 },
+    // mostly main cardioid, pixels run to the iteration limit unless the
+    // interior checks catch them
+    Scene { name: "interior", center: (-0.4, 0.0), zoom: 3.0 },
+];
+
+// Times of one backend on one scene.
+struct Row {
+    backend: &'static str,
+    scene: &'static str,
+    width: u32,
+    height: u32,
+    frames: Vec<Duration>,
+    // mean of every phase over the frames
+    phases: Timings,
+}
+
+impl Row {
+    fn min(&self) -> Duration {
+        *self.frames.iter().min().unwrap()
+    }
+
+    fn mean(&self) -> Duration {
+        self.frames.iter().sum::<Duration>() / self.frames.len() as u32
+    }
+
+    // pixels per microsecond is megapixels per second
+    fn mpixels_per_s(&self) -> f64 {
+        (self.width as f64 * self.height as f64) / (self.mean().as_secs_f64() * 1e6)
+    }
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1e3
+}
+
+// the standard scenes, or just the configured view when one was given
+fn scenes(options: &Options) -> Vec<(&'static str, Viewport)> {
+    if options.center.is_some() || options.zoom != 1.0 || options.julia.is_some() {
+        return vec![("custom", options.viewport())];
+    }
+    let overview = options.viewport();
+    SCENES.iter().map(|scene| {
+        let (x, y) = scene.center;
+        (scene.name, Viewport::new(x, y, overview.scale / scene.zoom, options.width, options.height))
+    }).collect()
+}
+
+fn bench_backend(name: &str, options: &Options, scenes: &[(&'static str, Viewport)], rows: &mut Vec<Row>) -> Result<(), String> {
+    let mut renderer = backend::by_name(name, &options.device)?;
+    let params = options.params();
+    for (scene, viewport) in scenes {
+        if let Err(err) = renderer.render(viewport, &params) {
+            eprintln!("{} failed on {}: {}", renderer.name(), scene, err);
+            continue;
+        }
+        let mut frames = Vec::new();
+        let mut sum = Timings::default();
+        for _ in 0..options.runs.max(1) {
+            let timer = Instant::now();
+            renderer.render(viewport, &params)?;
+            let frame = timer.elapsed();
+            let phases = renderer.timings().unwrap_or(Timings { kernel: frame, ..Timings::default() });
+            sum.setup += phases.setup;
+            sum.upload += phases.upload;
+            sum.kernel += phases.kernel;
+            sum.readback += phases.readback;
+            frames.push(frame);
+        }
+        let n = frames.len() as u32;
+        rows.push(Row {
+            backend: renderer.name(),
+            scene,
+            width: viewport.width,
+            height: viewport.height,
+            frames,
+            phases: Timings { setup: sum.setup / n, upload: sum.upload / n, kernel: sum.kernel / n, readback: sum.readback / n },
+        });
+    }
+    Ok(())
+}
+
+fn print_table(rows: &[Row]) {
+    println!("{:<13} {:<9} {:>10} {:>5} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9} {:>8}",
+        "backend", "scene", "size", "runs", "min ms", "mean ms", "setup", "upload", "kernel", "readback", "Mpix/s");
+    for row in rows {
+        println!("{:<13} {:<9} {:>10} {:>5} {:>9.3} {:>9.3} {:>9.3} {:>9.3} {:>9.3} {:>9.3} {:>8.1}",
+            row.backend, row.scene, format!("{}x{}", row.width, row.height), row.frames.len(),
+            ms(row.min()), ms(row.mean()), ms(row.phases.setup), ms(row.phases.upload),
+            ms(row.phases.kernel), ms(row.phases.readback), row.mpixels_per_s());
+    }
+}
+
+fn to_csv(rows: &[Row]) -> String {
+    let mut out = String::from("backend,scene,width,height,runs,min_ms,mean_ms,setup_ms,upload_ms,kernel_ms,readback_ms,mpixels_per_s\n");
+    for row in rows {
+        out += &format!("{},{},{},{},{},{:.6},{:.6},{:.6},{:.6},{:.6},{:.6},{:.3}\n",
+            row.backend, row.scene, row.width, row.height, row.frames.len(), ms(row.min()), ms(row.mean()),
+            ms(row.phases.setup), ms(row.phases.upload), ms(row.phases.kernel), ms(row.phases.readback),
+            row.mpixels_per_s());
+    }
+    out
+}
+
+// names are fixed identifiers, no escaping needed
+fn to_json(rows: &[Row]) -> String {
+    let items: Vec<String> = rows.iter().map(|row| format!(
+        "  {{\"backend\": \"{}\", \"scene\": \"{}\", \"width\": {}, \"height\": {}, \"runs\": {}, \"min_ms\": {:.6}, \"mean_ms\": {:.6}, \"setup_ms\": {:.6}, \"upload_ms\": {:.6}, \"kernel_ms\": {:.6}, \"readback_ms\": {:.6}, \"mpixels_per_s\": {:.3}}}",
+        row.backend, row.scene, row.width, row.height, row.frames.len(), ms(row.min()), ms(row.mean()),
+        ms(row.phases.setup), ms(row.phases.upload), ms(row.phases.kernel), ms(row.phases.readback),
+        row.mpixels_per_s(),
+    )).collect();
+    format!("[\n{}\n]\n", items.join(",\n"))
+}
+
+// picks the format from the file extension
+fn write_report(path: &str, rows: &[Row]) -> Result<(), String> {
+    let lower = path.to_lowercase();
+    let text = if lower.ends_with(".csv") {
+        to_csv(rows)
+    } else if lower.ends_with(".json") {
+        to_json(rows)
+    } else {
+        return Err(format!("{}: unknown report format, expected .csv or .json", path));
+    };
+    fs::write(path, text).map_err(|e| format!("{}: {}", path, e))
+}
+
+// Benchmarks the chosen backend, or every backend that can be opened, and
+// prints a table, `--report` also writes the results to a file.
+pub(crate) fn run(options: &Options) -> Result<(), String> {
+    let scenes = scenes(options);
+    let mut rows = Vec::new();
+    match &options.backend {
+        Some(name) => bench_backend(name, options, &scenes, &mut rows)?,
+        None => {
+            for name in BACKEND_NAMES {
+                if let Err(err) = bench_backend(name, options, &scenes, &mut rows) {
+                    eprintln!("skipping {}: {}", name, err);
+                }
+            }
+        }
+    }
+    if rows.is_empty() {
+        return Err("no backend could be benchmarked".to_string());
+    }
+
+    print_table(&rows);
+    if let Some(path) = &options.report {
+        write_report(path, &rows)?;
+        println!("wrote {}", path);
+    }
+    Ok(())
+}
@@ -0,0 +1,201 @@
+// View bookmarks: parameter files that lead back to a place.
+//
+// One setting per line, '#' starts a comment, like palette files:
+//
+//     center -0.7436438870371587047521915061147 0.1318259042053119704931320563851
+//     zoom 2.5e7
+//     iterations 5000
+//     formula mandelbrot
+//     julia -0.8 0.156
+//     palette fire
+//     backend perturbation
+//
+// The center holds every digit of the double-double viewport center, zoom is
+// relative to the overview so a bookmark fits any window size. Multibrot
+// takes its power as a second word, julia, palette and backend are optional.
+
+use std::fs;
+use std::path::Path;
+
+use crate::backend::{Formula, RenderParams, BACKEND_NAMES};
+use crate::dd::Dd;
+use crate::palette::setting_lines;
+use crate::viewport::Viewport;
+
+// directory the viewer saves bookmarks to
+pub(crate) const BOOKMARK_DIR: &str = "bookmarks";
+// number of quick bookmark slots, bound to F1 to F9 in the viewer
+pub(crate) const QUICK_SLOTS: u32 = 9;
+
+#[derive(Clone, Debug)]
+pub(crate) struct Bookmark {
+    pub(crate) center: (Dd, Dd),
+    pub(crate) zoom: f64,
+    pub(crate) max_iter: i32,
+    pub(crate) formula: Formula,
+    pub(crate) julia: Option<(f64, f64)>,
+    // see Palette::source
+    pub(crate) palette: Option<String>,
+    pub(crate) backend: Option<&'static str>,
+}
+
+impl Bookmark {
+    pub(crate) fn of_view(viewport: &Viewport, params: &RenderParams, palette: &str, backend: &'static str) -> Bookmark {
+        let overview = Viewport::overview_of(params.julia.is_some(), viewport.width, viewport.height);
+        Bookmark {
+            center: (viewport.center_x, viewport.center_y),
+            zoom: overview.scale / viewport.scale,
+            max_iter: params.max_iter,
+            formula: params.formula,
+            julia: params.julia,
+            palette: Some(palette.to_string()),
+            backend: Some(backend),
+        }
+    }
+
+    // the bookmarked view in a window of the given size
+    pub(crate) fn viewport(&self, width: u32, height: u32) -> Viewport {
+        let overview = Viewport::overview_of(self.julia.is_some(), width, height);
+        Viewport { center_x: self.center.0, center_y: self.center.1, scale: overview.scale / self.zoom, width, height }
+    }
+
+    pub(crate) fn load(path: &str) -> Result<Bookmark, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Bookmark::parse(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub(crate) fn save(&self, path: &str) -> Result<(), String> {
+        if let Some(dir) = Path::new(path).parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        }
+        fs::write(path, self.to_text()).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub(crate) fn parse(text: &str) -> Result<Bookmark, String> {
+        let mut center = None;
+        let mut zoom = None;
+        let mut max_iter = None;
+        let mut bookmark = Bookmark {
+            center: (Dd::default(), Dd::default()),
+            zoom: 1.0,
+            max_iter: 0,
+            formula: Formula::Mandelbrot,
+            julia: None,
+            palette: None,
+            backend: None,
+        };
+        for line in setting_lines(text) {
+            match line.name() {
+                "center" => center = Some((line.value(1)?, line.value(2)?)),
+                "zoom" => zoom = Some(line.value(1)?),
+                "iterations" => max_iter = Some(line.value(1)?),
+                "formula" => {
+                    let power = if line.len() > 2 { line.value(2)? } else { 3.0 };
+                    bookmark.formula = Formula::from_name(line.word(1)?, power).map_err(|e| line.err(&e))?;
+                }
+                "julia" => bookmark.julia = Some((line.value(1)?, line.value(2)?)),
+                "palette" => bookmark.palette = Some(line.rest(1)).filter(|p| !p.is_empty()),
+                "backend" => {
+                    let name = line.word(1)?;
+                    let known = BACKEND_NAMES.iter().find(|&&b| b == name);
+                    bookmark.backend = Some(*known.ok_or_else(|| line.err(&format!("unknown backend '{}'", name)))?);
+                }
+                other => return Err(line.err(&format!("unknown setting '{}'", other))),
+            }
+        }
+        bookmark.center = center.ok_or("missing center")?;
+        bookmark.zoom = zoom.ok_or("missing zoom")?;
+        bookmark.max_iter = max_iter.ok_or("missing iterations")?;
+        if bookmark.zoom <= 0.0 || bookmark.max_iter <= 0 {
+            return Err("zoom and iterations must be positive".to_string());
+        }
+        Ok(bookmark)
+    }
+
+    pub(crate) fn to_text(&self) -> String {
+        let mut text = format!("center {} {}\nzoom {:e}\niterations {}\n",
+            self.center.0.to_decimal(), self.center.1.to_decimal(), self.zoom, self.max_iter);
+        text += &match self.formula {
+            Formula::Multibrot(power) => format!("formula multibrot {}\n", power),
+            formula => format!("formula {}\n", formula.name()),
+        };
+        if let Some((cx, cy)) = self.julia {
+            text += &format!("julia {} {}\n", cx, cy);
+        }
+        if let Some(palette) = &self.palette {
+            text += &format!("palette {}\n", palette);
+        }
+        if let Some(backend) = self.backend {
+            text += &format!("backend {}\n", backend);
+        }
+        text
+    }
+}
+
+// file of a quick bookmark slot, 1 to QUICK_SLOTS
+pub(crate) fn quick_path(slot: u32) -> String {
+    format!("{}/quick-{}.txt", BOOKMARK_DIR, slot)
+}
+
+// the first bookmark-N.txt that does not exist yet
+pub(crate) fn next_free_path() -> String {
+    (1..)
+        .map(|n| format!("{}/bookmark-{}.txt", BOOKMARK_DIR, n))
+        .find(|path| !Path::new(path).exists())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VIEW: &str = "center -0.7436438870371587047521915061147 0.1318259042053119704931320563851\nzoom 2.5e7\niterations 5000\n";
+
+    #[test]
+    fn text_round_trip() {
+        let bookmark = Bookmark {
+            center: ("-0.7436438870371587047521915061147".parse().unwrap(), "1.5e-3".parse().unwrap()),
+            zoom: 2.5e7,
+            max_iter: 5000,
+            formula: Formula::Multibrot(3.5),
+            julia: Some((-0.8, 0.156)),
+            palette: Some("palettes/sunset.txt".to_string()),
+            backend: Some("perturbation"),
+        };
+        let parsed = Bookmark::parse(&bookmark.to_text()).unwrap();
+        assert_eq!(parsed.center, bookmark.center);
+        assert_eq!(parsed.zoom, bookmark.zoom);
+        assert_eq!(parsed.max_iter, bookmark.max_iter);
+        assert_eq!(parsed.formula, bookmark.formula);
+        assert_eq!(parsed.julia, bookmark.julia);
+        assert_eq!(parsed.palette, bookmark.palette);
+        assert_eq!(parsed.backend, bookmark.backend);
+    }
+
+    #[test]
+    fn optional_settings_default() {
+        let bookmark = Bookmark::parse(&format!("# seahorse valley\n{}\n", VIEW)).unwrap();
+        assert_eq!(bookmark.formula, Formula::Mandelbrot);
+        assert_eq!(bookmark.julia, None);
+        assert_eq!(bookmark.palette, None);
+        assert_eq!(bookmark.backend, None);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let cases = [
+            ("zoom 2\niterations 100", "missing center"),
+            ("center 0 0\niterations 100", "missing zoom"),
+            ("center 0 0\nzoom 2", "missing iterations"),
+            ("center 0 0\nzoom 0\niterations 100", "zoom and iterations must be positive"),
+            ("center 0 0\nzoom 2\niterations -5", "zoom and iterations must be positive"),
+            ("center 0\nzoom 2\niterations 100", "line 1: missing value"),
+            ("center 0 x\nzoom 2\niterations 100", "line 1: invalid number 'x'"),
+            ("center 0 0\nzoom 2\niterations 100\nbackend gpu", "line 4: unknown backend 'gpu'"),
+            ("center 0 0\ncolour red", "line 2: unknown setting 'colour'"),
+        ];
+        for (text, err) in cases {
+            assert_eq!(Bookmark::parse(text).unwrap_err(), err, "{:?}", text);
+        }
+    }
+}
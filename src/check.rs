@@ -0,0 +1,144 @@
+// Cross-backend consistency check.
+//
+// Renders a fixed set of sample points on every backend that can be opened,
+// with the interior shortcut and periodicity checking each on and off, and
+// compares the inside/outside classification against the known answer. The
+// points sit well away from the boundary, so single and double precision
+// agree on them. The perturbation backend also gets a deep zoom sample next
+// to a minibrot.
+
+use crate::backend::{self, Formula, RenderParams, BACKEND_NAMES};
+use crate::cli::Options;
+use crate::viewport::Viewport;
+
+// (x, y, inside the set)
+const SAMPLES: [(f64, f64, bool); 10] = [
+    (0.0, 0.0, true),
+    (0.24, 0.0, true),
+    (-0.5, 0.5, true),
+    (-1.0, 0.0, true),
+    (-1.2, 0.1, true),
+    // center of the period-3 component on the real axis, not covered by
+    // the shortcut
+    (-1.7549, 0.0, true),
+    // center of the period-3 bulb above the cardioid
+    (-0.1226, 0.7449, true),
+    (0.3, 0.0, false),
+    // between the cardioid and the period-2 bulb
+    (-0.75, 0.1, false),
+    (-2.1, 0.0, false),
+];
+
+// pixel spacing of the one pixel views used to sample a point
+const SAMPLE_SCALE: f64 = 1e-3;
+
+// Just outside the cusp of a period-117 minibrot about 4e-8 across. The
+// orbit follows the minibrot's cycle closer than float rounding for
+// thousands of iterations and escapes after 3489.
+const DEEP_SAMPLE: (f64, f64, bool) = (-0.7436455844081173, 0.13182456172445525, false);
+const DEEP_SCALE: f64 = 1e-11;
+const DEEP_MAX_ITER: i32 = 5000;
+
+// whether the backend finds the point inside the set
+fn classify(renderer: &mut dyn backend::Renderer, x: f64, y: f64, scale: f64, params: &RenderParams) -> Result<bool, String> {
+    // shift the center by half a pixel so the single pixel lands on (x, y)
+    let viewport = Viewport::new(x + scale / 2.0, y + scale / 2.0, scale, 1, 1);
+    let buffer = renderer.render(&viewport, params)?;
+    Ok(buffer.get(0, 0) >= params.max_iter)
+}
+
+// every sample with each combination of the interior shortcut and
+// periodicity checking, one line per wrong or failed sample
+fn mismatches(renderer: &mut dyn backend::Renderer, base: &RenderParams) -> Vec<String> {
+    let mut mismatches = Vec::new();
+    for (interior_check, periodicity) in [(true, true), (true, false), (false, true), (false, false)] {
+        let params = RenderParams { interior_check, periodicity, ..*base };
+        // the deep sample targets the perturbation kernel, the single precision
+        // backends cannot resolve it
+        let deep = (renderer.name() == "perturbation")
+            .then_some((DEEP_SAMPLE, DEEP_SCALE, RenderParams { max_iter: DEEP_MAX_ITER, ..params }));
+        let samples = SAMPLES.iter().map(|&sample| (sample, SAMPLE_SCALE, params)).chain(deep);
+        for ((x, y, inside), scale, params) in samples {
+            match classify(renderer, x, y, scale, &params) {
+                Ok(found) if found == inside => (),
+                Ok(found) => mismatches.push(format!("({}, {}) {} with interior check {}, periodicity {}",
+                    x, y, if found { "inside" } else { "outside" }, interior_check, periodicity)),
+                Err(err) => mismatches.push(format!("({}, {}) failed: {}", x, y, err)),
+            }
+        }
+    }
+    mismatches
+}
+
+pub(crate) fn run(options: &Options) -> Result<(), String> {
+    let base = RenderParams { julia: None, formula: Formula::Mandelbrot, ..options.params() };
+    let mut failures = 0;
+    let mut checked = 0;
+
+    for name in BACKEND_NAMES {
+        let mut renderer = match backend::by_name(name, &options.device) {
+            Ok(renderer) => renderer,
+            Err(err) => {
+                println!("{:<14} skipped: {}", name, err);
+                continue;
+            }
+        };
+        let mismatches = mismatches(renderer.as_mut(), &base);
+        checked += 1;
+        if mismatches.is_empty() {
+            println!("{:<14} ok", name);
+        } else {
+            println!("{:<14} {} mismatches", name, mismatches.len());
+            for mismatch in &mismatches {
+                println!("    {}", mismatch);
+            }
+            failures += 1;
+        }
+    }
+
+    if checked == 0 {
+        return Err("no backend could be opened".to_string());
+    }
+    if failures > 0 {
+        return Err(format!("{} of {} backends misclassified sample points", failures, checked));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::OPENCL_BACKENDS;
+    use crate::device::{self, DeviceFilter, DeviceType};
+
+    fn base() -> RenderParams {
+        RenderParams { julia: None, formula: Formula::Mandelbrot, ..Options::default().params() }
+    }
+
+    fn assert_classifies(name: &str, filter: &DeviceFilter) {
+        let mut renderer = backend::by_name(name, filter).unwrap();
+        let mismatches = mismatches(renderer.as_mut(), &base());
+        assert!(mismatches.is_empty(), "{}:\n{}", name, mismatches.join("\n"));
+    }
+
+    #[test]
+    fn cpu_backends_classify_samples() {
+        for name in ["cpu", "cpu64"] {
+            assert_classifies(name, &DeviceFilter::default());
+        }
+    }
+
+    // Without any OpenCL device this returns early and passes without
+    // checking anything, the skip is printed to stderr.
+    #[test]
+    fn opencl_backends_classify_samples() {
+        let filter = DeviceFilter { kind: Some(DeviceType::All), ..DeviceFilter::default() };
+        if let Err(err) = device::select(&filter) {
+            eprintln!("skipping the OpenCL backends: {}", err);
+            return;
+        }
+        for name in OPENCL_BACKENDS {
+            assert_classifies(name, &filter);
+        }
+    }
+}
@@ -0,0 +1,276 @@
+// Command line parsing.
+
+use crate::backend::{Formula, Precision, RenderParams};
+use crate::bookmark::Bookmark;
+use crate::dd::Dd;
+use crate::device::{DeviceFilter, DeviceType};
+use crate::supersample::{Pattern, Sampling, MAX_SAMPLES};
+use crate::viewport::Viewport;
+
+pub(crate) const USAGE: &str = "\
+usage: mandelbrot <command> [options]
+
+commands:
+    view        open the interactive viewer (default)
+    render      render one image to a file, no display needed
+    devices     list OpenCL platforms and devices
+    bench       time every backend on standard scenes, or on the --center/--zoom view
+    check       verify every backend classifies known points correctly
+    animate     render a keyframe script as an image sequence or .y4m video
+    help        show this message
+
+options:
+    --center X,Y        center of the view, any number of digits [-0.5,0, 0,0 for julia]
+    --zoom Z            magnification relative to the full set, perturbation
+                        reaches about 4e27 around |center| = 1 [1]
+    --size WxH          image or window size in pixels [900x600]
+    --iterations N      maximum iteration count [1000]
+    --backend NAME      ocl, opencl3, cpu, cpu64 or perturbation [opencl3, cpu for render]
+    --precision P       auto, single or double [auto]
+    --palette NAME      grey, fire, ocean, rainbow or a palette file [grey]
+    --smooth            fractional escape counts for band-free colouring
+    --julia X,Y         render the Julia set of c = X+Yi instead [off]
+    --formula NAME      mandelbrot, multibrot, burning-ship, tricorn or celtic [mandelbrot]
+    --power D           exponent of multibrot, any real number [3]
+    --no-interior-check iterate cardioid and period-2 bulb points instead of skipping them
+    --no-periodicity    iterate cycling orbits to the limit instead of stopping early
+    --periods           colour interior points by the cycle length of their orbit
+    --samples N         supersampling, N x N samples per pixel, at most 16 [1]
+    --sampling P        grid or jitter sample pattern [grid]
+    --load FILE         start from a bookmark file, later options override it
+    --output FILE       image written by render, .png or .ppm, animate numbers
+                        the frames or writes one .y4m stream [mandelbrot.png]
+    --script FILE       keyframe script for animate
+    --runs N            repetitions for bench [5]
+    --report FILE       bench results written as .csv or .json [off]
+    --platform N        OpenCL platform index, see 'devices' [any]
+    --device N|NAME     device index within the platform or part of its name [first]
+    --device-type T     gpu, cpu or all [gpu, all with --platform or --device]
+    --json              machine readable output for devices
+    --detailed          full OpenCL object dump for devices
+";
+
+#[derive(Clone, Debug)]
+pub(crate) struct Options {
+    // None keeps the center of the overview
+    pub(crate) center: Option<(Dd, Dd)>,
+    pub(crate) zoom: f64,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) max_iter: i32,
+    pub(crate) backend: Option<String>,
+    pub(crate) precision: Precision,
+    pub(crate) palette: String,
+    pub(crate) smooth: bool,
+    pub(crate) output: String,
+    pub(crate) runs: u32,
+    pub(crate) report: Option<String>,
+    pub(crate) script: Option<String>,
+    pub(crate) julia: Option<(f64, f64)>,
+    pub(crate) formula: Formula,
+    pub(crate) interior_check: bool,
+    pub(crate) periodicity: bool,
+    pub(crate) periods: bool,
+    pub(crate) sampling: Sampling,
+    pub(crate) device: DeviceFilter,
+    pub(crate) json: bool,
+    pub(crate) detailed: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            center: None,
+            zoom: 1.0,
+            width: 900,
+            height: 600,
+            max_iter: 1000,
+            backend: None,
+            precision: Precision::Auto,
+            palette: "grey".to_string(),
+            smooth: false,
+            output: "mandelbrot.png".to_string(),
+            runs: 5,
+            report: None,
+            script: None,
+            julia: None,
+            formula: Formula::Mandelbrot,
+            interior_check: true,
+            periodicity: true,
+            periods: false,
+            sampling: Sampling::default(),
+            device: DeviceFilter::default(),
+            json: false,
+            detailed: false,
+        }
+    }
+}
+
+impl Options {
+    pub(crate) fn viewport(&self) -> Viewport {
+        let overview = Viewport::overview_of(self.julia.is_some(), self.width, self.height);
+        let (center_x, center_y) = self.center.unwrap_or((overview.center_x, overview.center_y));
+        Viewport { center_x, center_y, scale: overview.scale / self.zoom, width: self.width, height: self.height }
+    }
+
+    pub(crate) fn params(&self) -> RenderParams {
+        RenderParams { max_iter: self.max_iter, precision: self.precision, smooth: self.smooth, julia: self.julia, formula: self.formula,
+            interior_check: self.interior_check, periodicity: self.periodicity, periods: self.periods, jitter: false }
+    }
+
+    pub(crate) fn backend_or<'a>(&'a self, default: &'a str) -> &'a str {
+        self.backend.as_deref().unwrap_or(default)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) enum Command {
+    View(Options),
+    Render(Options),
+    Devices(Options),
+    Bench(Options),
+    Check(Options),
+    Animate(Options),
+    Help,
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String>
+where
+    T::Err: std::fmt::Display,
+{
+    value.trim().parse::<T>().map_err(|e| format!("invalid value '{}' for {}: {}", value, flag, e))
+}
+
+fn parse_pair<T: std::str::FromStr>(flag: &str, value: &str, sep: char) -> Result<(T, T), String>
+where
+    T::Err: std::fmt::Display,
+{
+    match value.split_once(sep) {
+        Some((a, b)) => Ok((parse_number(flag, a)?, parse_number(flag, b)?)),
+        None => Err(format!("invalid value '{}' for {}, expected two values separated by '{}'", value, flag, sep)),
+    }
+}
+
+pub(crate) fn parse(args: &[String]) -> Result<Command, String> {
+    let mut args = args.iter().peekable();
+    let command = match args.peek().map(|s| s.as_str()) {
+        Some(name) if !name.starts_with('-') => {
+            args.next();
+            name.to_string()
+        }
+        _ => "view".to_string(),
+    };
+
+    let mut options = Options::default();
+    let mut formula = "mandelbrot".to_string();
+    let mut power = 3.0;
+    let mut power_given = false;
+    while let Some(flag) = args.next() {
+        let flag = flag.as_str();
+        if flag == "--help" || flag == "-h" {
+            return Ok(Command::Help);
+        }
+        // switches without a value
+        let switch = match flag {
+            "--smooth" => Some(&mut options.smooth),
+            "--json" => Some(&mut options.json),
+            "--detailed" => Some(&mut options.detailed),
+            "--periods" => Some(&mut options.periods),
+            _ => None,
+        };
+        if let Some(switch) = switch {
+            *switch = true;
+            continue;
+        }
+        // switches turning a default off
+        let switch = match flag {
+            "--no-interior-check" => Some(&mut options.interior_check),
+            "--no-periodicity" => Some(&mut options.periodicity),
+            _ => None,
+        };
+        if let Some(switch) = switch {
+            *switch = false;
+            continue;
+        }
+        let value = args.next().ok_or_else(|| format!("missing value for {}", flag))?;
+        match flag {
+            "--center" | "-c" => options.center = Some(parse_pair(flag, value, ',')?),
+            "--zoom" | "-z" => options.zoom = parse_number(flag, value)?,
+            "--size" | "-s" => {
+                let (width, height) = parse_pair(flag, value, 'x')?;
+                options.width = width;
+                options.height = height;
+            }
+            "--iterations" | "-i" => options.max_iter = parse_number(flag, value)?,
+            "--backend" | "-b" => options.backend = Some(value.clone()),
+            "--precision" => options.precision = Precision::from_name(value)?,
+            "--palette" | "-p" => options.palette = value.clone(),
+            "--output" | "-o" => options.output = value.clone(),
+            "--runs" => options.runs = parse_number(flag, value)?,
+            "--report" => options.report = Some(value.clone()),
+            "--script" => options.script = Some(value.clone()),
+            "--samples" => options.sampling.samples = parse_number(flag, value)?,
+            "--sampling" => options.sampling.pattern = Pattern::from_name(value)?,
+            "--load" => {
+                // later options override the bookmark
+                let bookmark = Bookmark::load(value)?;
+                options.center = Some(bookmark.center);
+                options.zoom = bookmark.zoom;
+                options.max_iter = bookmark.max_iter;
+                options.julia = bookmark.julia;
+                formula = bookmark.formula.name().to_string();
+                if let Formula::Multibrot(p) = bookmark.formula {
+                    power = p;
+                }
+                if let Some(palette) = bookmark.palette {
+                    options.palette = palette;
+                }
+                if let Some(backend) = bookmark.backend {
+                    options.backend = Some(backend.to_string());
+                }
+            }
+            "--formula" | "-f" => formula = value.clone(),
+            "--power" => {
+                power = parse_number(flag, value)?;
+                power_given = true;
+            }
+            "--julia" | "-j" => options.julia = Some(parse_pair(flag, value, ',')?),
+            "--platform" => options.device.platform = Some(parse_number(flag, value)?),
+            "--device" | "-d" => options.device.device = Some(value.clone()),
+            "--device-type" => options.device.kind = Some(DeviceType::from_name(value)?),
+            _ => return Err(format!("unknown option '{}'", flag)),
+        }
+    }
+
+    options.formula = Formula::from_name(&formula, power)?;
+    if power_given && !matches!(options.formula, Formula::Multibrot(_)) {
+        return Err("--power only applies to --formula multibrot".to_string());
+    }
+
+    if options.width == 0 || options.height == 0 {
+        return Err("--size must be at least 1x1".to_string());
+    }
+    if options.periods && !options.periodicity {
+        return Err("--periods needs the periodicity check, drop --no-periodicity".to_string());
+    }
+    if options.sampling.samples == 0 || options.sampling.samples > MAX_SAMPLES {
+        return Err(format!("--samples must be between 1 and {}", MAX_SAMPLES));
+    }
+    if !(options.zoom.is_finite() && options.zoom > 0.0) {
+        return Err("--zoom must be a positive finite number".to_string());
+    }
+    if options.max_iter <= 0 {
+        return Err("--iterations must be positive".to_string());
+    }
+
+    match command.as_str() {
+        "view" => Ok(Command::View(options)),
+        "render" => Ok(Command::Render(options)),
+        "devices" => Ok(Command::Devices(options)),
+        "bench" => Ok(Command::Bench(options)),
+        "check" => Ok(Command::Check(options)),
+        "animate" => Ok(Command::Animate(options)),
+        "help" => Ok(Command::Help),
+        _ => Err(format!("unknown command '{}'", command)),
+    }
+}
@@ -1,8 +1,16 @@
 extern crate ocl;
 
-use std::mem::size_of_val;
-use std::time::Instant;
-use ocl::{Buffer, ProQue};
+use std::mem::{size_of, size_of_val};
+use std::time::{Duration, Instant};
+use ocl::core::{DeviceInfo, DeviceInfoResult};
+use ocl::{Buffer, Device, Kernel, ProQue, Program};
+
+use crate::backend::{IterationBuffer, Precision, RenderParams, Renderer, Timings};
+use crate::device::{self, DeviceFilter};
+use crate::info;
+use crate::kernel;
+use crate::kernel::{DOUBLE_OPTIONS, KERNEL_NAME, PRELUDE, PROGRAM_SOURCE};
+use crate::viewport::Viewport;
 
 pub(crate) fn trivial() -> ocl::Result<()> {
     let src = r#"
@@ -39,85 +47,200 @@ pub(crate) fn trivial() -> ocl::Result<()> {
     Ok(())
 }
 
-pub(crate) unsafe fn mandelbrot(vec: &mut Vec<i32>, res: u32, iter: i32) -> ocl::Result<()> {
-    let src = r#"
-        __kernel void mandelbrot(__global float* X, __global float* Y, __global int* RET, int iter) {
-            int id = get_global_id(0);
-            float x0 = X[id];
-            float y0 = Y[id];
-            float x = 0;
-            float y = 0;
-            float x2 = 0;
-            float y2 = 0;
-            int it = 0;
-
-            float q = pow((x0 - (1/4)), 2) + pow(y0, 2);
-            if (q*(q + (x + (1/4))) < (1/4) * pow(y0, 2)) {
-                RET[id] = iter;
-                return;
-            }
-
-            while (pow(x, 2) + pow(y, 2) <= 4 && it < iter) {
-                y = 2*x*y + y0;
-                x = x2 - y2 + x0;
-                x2 = x*x;
-                y2 = y*y;
-                it = it + 1;
-            }
-
-            RET[id] = it;
-        }
-    "#;
+// Device buffer and the kernel bound to it, sized for one frame.
+struct FrameBuffers {
+    width: u32,
+    height: u32,
+    double: bool,
+    ret: Buffer<i32>,
+    smooth: Buffer<f32>,
+    period: Buffer<i32>,
+    kernel: Kernel,
+}
 
-    let pro_que = ProQue::builder()
-        .src(src)
-        .build()?;
+// Long-lived ocl state, the programs are compiled once and the buffer is
+// only reallocated when the frame size or precision changes.
+pub(crate) struct OclRenderer {
+    pro_que: ProQue,
+    // double precision build of the program, compiled on first use
+    program64: Option<Program>,
+    fp64: bool,
+    buffers: Option<FrameBuffers>,
+    timings: Timings,
+}
 
-    let mut vec_x:Vec<f32> = Vec::new();
-    let mut vec_y:Vec<f32> = Vec::new();
+impl OclRenderer {
+    pub(crate) fn new(filter: &DeviceFilter) -> Result<OclRenderer, String> {
+        let entry = device::select(filter)?;
+        let platform = info::platforms().map_err(|e| e.to_string())?[entry.platform];
+        let device = Device::list_all(platform).map_err(|e| e.to_string())?[entry.index];
+        let mut program = Program::builder();
+        program.src(PRELUDE).src(PROGRAM_SOURCE);
+        let pro_que = ProQue::builder()
+            .platform(platform)
+            .device(device)
+            .prog_bldr(program)
+            .build()
+            .map_err(|e| e.to_string())?;
+        let fp64 = info::supports_fp64(&pro_que.device()).map_err(|e| e.to_string())?;
+
+        Ok(OclRenderer { pro_que, program64: None, fp64, buffers: None, timings: Timings::default() })
+    }
 
-    for i in 0..3 * res {
-        for j in 0..2 * res {
-            vec_x.push((i as f32)/(res as f32) - 2.0);
-            vec_y.push((j as f32)/(res as f32) - 1.0);
+    fn ensure_buffers(&mut self, width: u32, height: u32, double: bool) -> ocl::Result<()> {
+        if self.buffers.as_ref().is_none_or(|b| (b.width, b.height, b.double) != (width, height, double)) {
+            let ret = Buffer::<i32>::builder()
+                .queue(self.pro_que.queue().clone())
+                .flags(ocl::flags::MEM_WRITE_ONLY)
+                .len(width as usize * height as usize)
+                .build()?;
+
+            let smooth = Buffer::<f32>::builder()
+                .queue(self.pro_que.queue().clone())
+                .flags(ocl::flags::MEM_WRITE_ONLY)
+                .len(width as usize * height as usize)
+                .build()?;
+
+            let period = Buffer::<i32>::builder()
+                .queue(self.pro_que.queue().clone())
+                .flags(ocl::flags::MEM_WRITE_ONLY)
+                .len(width as usize * height as usize)
+                .build()?;
+
+            let kernel = if double {
+                if self.program64.is_none() {
+                    self.program64 = Some(Program::builder()
+                        .src(PRELUDE)
+                        .src(PROGRAM_SOURCE)
+                        .cmplr_opt(DOUBLE_OPTIONS)
+                        .devices(self.pro_que.device())
+                        .build(self.pro_que.context())?);
+                }
+                Kernel::builder()
+                    .name(KERNEL_NAME)
+                    .program(self.program64.as_ref().unwrap())
+                    .queue(self.pro_que.queue().clone())
+                    .arg(0f64)
+                    .arg(0f64)
+                    .arg(0f64)
+                    .arg(&ret)
+                    .arg(&smooth)
+                    .arg(0i32)
+                    .arg(0f64)
+                    .arg(0i32)
+                    .arg(0f64)
+                    .arg(0f64)
+                    .arg(0i32)
+                    .arg(0f64)
+                    .arg(0i32)
+                    .arg(&period)
+                    .arg(0i32)
+                    .arg(0i32)
+                    .global_work_size((width as usize, height as usize))
+                    .build()?
+            } else {
+                self.pro_que.kernel_builder(KERNEL_NAME)
+                    .arg(0f32)
+                    .arg(0f32)
+                    .arg(0f32)
+                    .arg(&ret)
+                    .arg(&smooth)
+                    .arg(0i32)
+                    .arg(0f32)
+                    .arg(0i32)
+                    .arg(0f32)
+                    .arg(0f32)
+                    .arg(0i32)
+                    .arg(0f32)
+                    .arg(0i32)
+                    .arg(&period)
+                    .arg(0i32)
+                    .arg(0i32)
+                    .global_work_size((width as usize, height as usize))
+                    .build()?
+            };
+
+            self.buffers = Some(FrameBuffers { width, height, double, ret, smooth, period, kernel });
         }
+        Ok(())
     }
-    let buffer_x = Buffer::<f32>::builder()
-        .queue(pro_que.queue().clone())
-        .flags(ocl::flags::MEM_READ_WRITE)
-        .len(vec_x.len())
-        .copy_host_slice(&vec_x)
-        .build()?;
-    
-    let buffer_y = Buffer::<f32>::builder()
-        .queue(pro_que.queue().clone())
-        .flags(ocl::flags::MEM_READ_WRITE)
-        .len(vec_y.len())
-        .copy_host_slice(&vec_y)
-        .build()?;
 
-    let buffer_ret = Buffer::<i32>::builder()
-        .queue(pro_que.queue().clone())
-        .flags(ocl::flags::MEM_READ_WRITE)
-        .len(vec.len())
-        .use_host_slice(vec)
-        .build()?;
+    // the queue has no profiling, the phases are timed on the host
+    pub(crate) fn render_into(&mut self, out: &mut IterationBuffer, viewport: &Viewport, params: &RenderParams, double: bool) -> ocl::Result<()> {
+        let timer = Instant::now();
+        self.ensure_buffers(viewport.width, viewport.height, double)?;
+        let buffers = self.buffers.as_ref().unwrap();
+        let (cx, cy) = params.julia.unwrap_or_default();
+
+        if double {
+            buffers.kernel.set_arg(0, viewport.x_min())?;
+            buffers.kernel.set_arg(1, viewport.y_min())?;
+            buffers.kernel.set_arg(2, viewport.scale)?;
+            buffers.kernel.set_arg(6, params.bailout())?;
+            buffers.kernel.set_arg(8, cx)?;
+            buffers.kernel.set_arg(9, cy)?;
+            buffers.kernel.set_arg(11, params.formula.power())?;
+        } else {
+            buffers.kernel.set_arg(0, viewport.x_min() as f32)?;
+            buffers.kernel.set_arg(1, viewport.y_min() as f32)?;
+            buffers.kernel.set_arg(2, viewport.scale as f32)?;
+            buffers.kernel.set_arg(6, params.bailout() as f32)?;
+            buffers.kernel.set_arg(8, cx as f32)?;
+            buffers.kernel.set_arg(9, cy as f32)?;
+            buffers.kernel.set_arg(11, params.formula.power() as f32)?;
+        }
+        buffers.kernel.set_arg(5, params.max_iter)?;
+        buffers.kernel.set_arg(7, params.julia.is_some() as i32)?;
+        buffers.kernel.set_arg(10, params.formula.kernel_id())?;
+        buffers.kernel.set_arg(12, params.interior_check as i32)?;
+        buffers.kernel.set_arg(14, params.periodicity as i32)?;
+        buffers.kernel.set_arg(15, params.jitter as i32)?;
+
+        let setup = timer.elapsed();
+        let timer = Instant::now();
+        unsafe { buffers.kernel.enq()?; }
+        self.pro_que.queue().finish()?;
+        let kernel = timer.elapsed();
+
+        let timer = Instant::now();
+        buffers.ret.read(&mut out.data).enq()?;
+        if let Some(smooth) = out.smooth.as_mut() {
+            buffers.smooth.read(smooth).enq()?;
+        }
+        if let Some(period) = out.period.as_mut() {
+            buffers.period.read(period).enq()?;
+        }
+        self.timings = Timings { setup, upload: Duration::ZERO, kernel, readback: timer.elapsed() };
+        Ok(())
+    }
+}
 
-    let mut kernel = pro_que.kernel_builder("mandelbrot")
-        .arg(&buffer_x)
-        .arg(&buffer_y)
-        .arg(&buffer_ret)
-        .arg(iter)
-        .global_work_size(vec.len())
-        .build()?;
+impl Renderer for OclRenderer {
+    fn name(&self) -> &'static str {
+        "ocl"
+    }
 
-    println!("{}", vec_x.len());
-    println!("{}", vec.len());
+    // every frame buffer holds 4 byte elements
+    fn max_pixels(&self) -> Option<usize> {
+        match self.pro_que.device().info(DeviceInfo::MaxMemAllocSize) {
+            Ok(DeviceInfoResult::MaxMemAllocSize(bytes)) => Some(bytes as usize / size_of::<i32>()),
+            _ => None,
+        }
+    }
 
-    let timer = Instant::now();
-    kernel.enq()?;
+    fn timings(&self) -> Option<Timings> {
+        Some(self.timings)
+    }
 
-    buffer_ret.read(vec).enq()?;
-    println!("calq took {}", timer.elapsed().as_nanos());
-    Ok(())
-}
\ No newline at end of file
+    fn render(&mut self, viewport: &Viewport, params: &RenderParams) -> Result<IterationBuffer, String> {
+        let double = params.precision.resolve(viewport) == Precision::Double;
+        if double && !self.fp64 {
+            let name = self.pro_que.device().name().map_err(|e| e.to_string())?;
+            return Err(kernel::fp64_unavailable(&name));
+        }
+
+        let mut buffer = IterationBuffer::for_params(viewport.width, viewport.height, params);
+        self.render_into(&mut buffer, viewport, params, double).map_err(|e| e.to_string())?;
+        Ok(buffer)
+    }
+}
@@ -0,0 +1,216 @@
+use num_traits::Float;
+
+use crate::backend::{Formula, IterationBuffer, Precision, RenderParams, Renderer};
+use crate::supersample;
+use crate::viewport::Viewport;
+
+pub(crate) struct CpuRenderer {
+    // Auto follows the precision asked for in the render params
+    precision: Precision,
+}
+
+impl CpuRenderer {
+    pub(crate) fn new(precision: Precision) -> CpuRenderer {
+        CpuRenderer { precision }
+    }
+
+    // renders every pixel, or with `coarse` only those not on even rows and
+    // columns, see Renderer::render_cancellable, None when `cancelled`
+    // returned true before one of the rows
+    fn render_pixels(&self, viewport: &Viewport, params: &RenderParams, coarse: Option<&IterationBuffer>,
+                     cancelled: &dyn Fn() -> bool) -> Option<IterationBuffer> {
+        let precision = match self.precision {
+            Precision::Auto => params.precision,
+            p => p,
+        };
+        let double = precision.resolve(viewport) == Precision::Double;
+
+        let degree = params.formula.power().abs();
+        // orbit points this close count as the same for cycle detection
+        let tolerance = viewport.scale / 1024.0;
+
+        let mut buffer = IterationBuffer::for_params(viewport.width, viewport.height, params);
+        for j in 0..viewport.height {
+            if cancelled() {
+                return None;
+            }
+            for i in 0..viewport.width {
+                let idx = viewport.index(i, j);
+                if let Some(coarse) = coarse.filter(|_| i % 2 == 0 && j % 2 == 0) {
+                    let from = (j / 2) as usize * coarse.width as usize + (i / 2) as usize;
+                    buffer.data[idx] = coarse.data[from];
+                    if let (Some(smooth), Some(known)) = (buffer.smooth.as_mut(), coarse.smooth.as_ref()) {
+                        smooth[idx] = known[from];
+                    }
+                    if let (Some(periods), Some(known)) = (buffer.period.as_mut(), coarse.period.as_ref()) {
+                        periods[idx] = known[from];
+                    }
+                    continue;
+                }
+
+                let (di, dj) = if params.jitter { supersample::jitter(i, j) } else { (0.0, 0.0) };
+                let (x0, y0) = viewport.pixel_to_complex(i as f64 + di, j as f64 + dj);
+                let (it, log_z, period) = if double {
+                    iterate(x0, y0, params.julia, params, params.bailout(), tolerance)
+                } else {
+                    let julia = params.julia.map(|(cx, cy)| (cx as f32, cy as f32));
+                    let (it, log_z, period) = iterate(x0 as f32, y0 as f32, julia, params,
+                        params.bailout() as f32, tolerance as f32);
+                    (it, log_z as f64, period)
+                };
+                buffer.data[idx] = it;
+                if let Some(smooth) = buffer.smooth.as_mut() {
+                    smooth[idx] = smooth_count(it, params.max_iter, log_z, degree);
+                }
+                if let Some(periods) = buffer.period.as_mut() {
+                    periods[idx] = period;
+                }
+            }
+        }
+        Some(buffer)
+    }
+
+    // `coarse` if it can stand in for the even pixels of `viewport`
+    fn usable<'a>(viewport: &Viewport, params: &RenderParams, coarse: Option<&'a IterationBuffer>) -> Option<&'a IterationBuffer> {
+        coarse.filter(|coarse| coarse.width == viewport.width.div_ceil(2)
+            && coarse.height == viewport.height.div_ceil(2)
+            && coarse.smooth.is_some() == params.smooth
+            && coarse.period.is_some() == params.periods
+            && !params.jitter)
+    }
+}
+
+impl Renderer for CpuRenderer {
+    fn name(&self) -> &'static str {
+        match self.precision {
+            Precision::Double => "cpu64",
+            _ => "cpu",
+        }
+    }
+
+    fn render(&mut self, viewport: &Viewport, params: &RenderParams) -> Result<IterationBuffer, String> {
+        Ok(self.render_pixels(viewport, params, None, &|| false).expect("never cancelled"))
+    }
+
+    // checks `cancelled` before every row
+    fn render_cancellable(&mut self, viewport: &Viewport, params: &RenderParams, coarse: Option<&IterationBuffer>,
+                          cancelled: &dyn Fn() -> bool) -> Result<Option<IterationBuffer>, String> {
+        let coarse = CpuRenderer::usable(viewport, params, coarse);
+        Ok(self.render_pixels(viewport, params, coarse, cancelled))
+    }
+}
+
+// it + 1 - log_d(log|z|), continuous across the iteration bands of a
+// degree d formula
+fn smooth_count(it: i32, max_it: i32, log_z: f64, degree: f64) -> f32 {
+    if it >= max_it {
+        return max_it as f32;
+    }
+    if degree <= 1.0 {
+        return it as f32;
+    }
+    (it as f64 + 1.0 - log_z.log2() / degree.log2()) as f32
+}
+
+// log|z| of the escaped point from its squared magnitude, or when that
+// overflowed from the one before, |z| ~ |z_prev|^power
+fn escaped_log<T: Float>(mag: T, prev: T, power: T) -> T {
+    let two = T::from(2.0).unwrap();
+    if mag.is_finite() { mag.ln() / two } else { power * prev.ln() / two }
+}
+
+// main cardioid and period-2 bulb, both lie entirely inside the set,
+// returns the period of the component or 0 outside of both
+fn in_main_bulbs<T: Float>(x: T, y: T) -> i32 {
+    let quarter = T::from(0.25).unwrap();
+    let xq = x - quarter;
+    let q = xq*xq + y*y;
+    if q*(q + xq) <= quarter*y*y {
+        return 1;
+    }
+    if (x + T::one()).powi(2) + y*y <= T::from(0.0625).unwrap() {
+        return 2;
+    }
+    0
+}
+
+// z -> f(z) + c, the same arithmetic as apply_formula in the kernel
+fn apply_formula<T: Float>(formula: Formula, x: T, y: T, cx: T, cy: T) -> (T, T) {
+    let two = T::from(2.0).unwrap();
+    let (nx, ny) = match formula {
+        Formula::Mandelbrot => (x*x - y*y, two*x*y),
+        Formula::Multibrot(power) => {
+            // exact products for small integer powers
+            if power == power.floor() && (2.0..=64.0).contains(&power) {
+                let (mut nx, mut ny) = (x, y);
+                for _ in 1..power as i32 {
+                    let t = nx*x - ny*y;
+                    ny = nx*y + ny*x;
+                    nx = t;
+                }
+                (nx, ny)
+            } else if x == T::zero() && y == T::zero() {
+                (T::zero(), T::zero())
+            } else {
+                let power = T::from(power).unwrap();
+                let r = (x*x + y*y).powf(power / two);
+                let theta = y.atan2(x) * power;
+                (r * theta.cos(), r * theta.sin())
+            }
+        }
+        Formula::BurningShip => {
+            let (x, y) = (x.abs(), y.abs());
+            (x*x - y*y, two*x*y)
+        }
+        Formula::Tricorn => (x*x - y*y, -two*x*y),
+        Formula::Celtic => ((x*x - y*y).abs(), two*x*y),
+    };
+    (nx + cx, ny + cy)
+}
+
+// escape count, log|z| of the last orbit point and detected period (0 if
+// none), with `julia` set (x0, y0) is the starting point and
+// the pair the constant c
+fn iterate<T: Float>(x0: T, y0: T, julia: Option<(T, T)>, params: &RenderParams, bailout: T, tolerance: T) -> (i32, T, i32) {
+    let zero = T::zero();
+    let formula = params.formula;
+    let max_it = params.max_iter;
+
+    let (mut x, mut y, x0, y0) = match julia {
+        Some((cx, cy)) => (x0, y0, cx, cy),
+        None => (zero, zero, x0, y0),
+    };
+    let mut it = 0;
+
+    //check if in main bulbs
+    if params.interior_check && julia.is_none() && formula == Formula::Mandelbrot {
+        let period = in_main_bulbs(x0, y0);
+        if period > 0 {
+            return (max_it, zero, period);
+        }
+    }
+
+    //escape time algorithm, with Brent cycle detection against a saved
+    //orbit point that is replaced after 1, 2, 4, ... steps
+    let (mut sx, mut sy) = (x, y);
+    let mut steps = 0;
+    let mut limit = 1;
+    let mut prev = zero;
+    while x*x + y*y <= bailout && it < max_it {
+        prev = x*x + y*y;
+        (x, y) = apply_formula(formula, x, y, x0, y0);
+        it += 1;
+        if params.periodicity {
+            steps += 1;
+            if (x - sx).abs() < tolerance && (y - sy).abs() < tolerance {
+                return (max_it, zero, steps);
+            }
+            if steps == limit {
+                (sx, sy) = (x, y);
+                steps = 0;
+                limit *= 2;
+            }
+        }
+    }
+    (it, escaped_log(x*x + y*y, prev, T::from(formula.power()).unwrap()), 0)
+}
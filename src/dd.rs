@@ -0,0 +1,291 @@
+// Double-double numbers: an unevaluated sum hi + lo of two f64 with
+// |lo| <= ulp(hi) / 2, giving about 106 bits (32 decimal digits) of mantissa.
+//
+// Used for the viewport center and the perturbation reference orbit, where
+// f64 runs out of digits around zooms of 1e-15.
+
+use std::ops::{Add, Div, Mul, Neg, Sub};
+use std::str::FromStr;
+
+// significant digits written by to_decimal, about all a Dd holds
+const DECIMAL_DIGITS: usize = 32;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct Dd {
+    pub(crate) hi: f64,
+    pub(crate) lo: f64,
+}
+
+// exact a + b as hi + lo
+fn two_sum(a: f64, b: f64) -> Dd {
+    let hi = a + b;
+    let bb = hi - a;
+    let lo = (a - (hi - bb)) + (b - bb);
+    Dd { hi, lo }
+}
+
+fn quick_two_sum(a: f64, b: f64) -> Dd {
+    let hi = a + b;
+    Dd { hi, lo: b - (hi - a) }
+}
+
+// exact a * b as hi + lo
+fn two_prod(a: f64, b: f64) -> Dd {
+    let hi = a * b;
+    Dd { hi, lo: a.mul_add(b, -hi) }
+}
+
+// 10^n, exact while it fits in 106 bits
+fn pow10(n: u32) -> Dd {
+    let mut p = Dd::from(1.0);
+    for _ in 0..n {
+        p = p * 10.0;
+    }
+    p
+}
+
+impl Dd {
+    // relative precision, 2^-104
+    pub(crate) const EPSILON: f64 = f64::EPSILON * f64::EPSILON;
+
+    pub(crate) fn to_f64(self) -> f64 {
+        self.hi + self.lo
+    }
+
+    pub(crate) fn sqr(self) -> Dd {
+        self * self
+    }
+
+    // decimal text with DECIMAL_DIGITS significant digits, plain for
+    // moderate exponents and in e notation otherwise, parses back to the
+    // value to within the precision of a Dd
+    pub(crate) fn to_decimal(self) -> String {
+        if self.hi == 0.0 || !self.hi.is_finite() {
+            return self.hi.to_string();
+        }
+        let negative = self.hi < 0.0;
+        let mut x = if negative { -self } else { self };
+
+        // scale into [1, 10)
+        let mut exponent = x.hi.log10().floor() as i32;
+        let scale = pow10(exponent.unsigned_abs());
+        x = if exponent < 0 { x * scale } else { x / scale };
+        if x.hi >= 10.0 {
+            x = x / Dd::from(10.0);
+            exponent += 1;
+        } else if x.hi < 1.0 {
+            x = x * 10.0;
+            exponent -= 1;
+        }
+
+        // one digit more than needed for rounding, float error can leave
+        // digits just outside 0..=9, which the carry pass below fixes
+        let mut digits = Vec::with_capacity(DECIMAL_DIGITS + 1);
+        for _ in 0..=DECIMAL_DIGITS {
+            let d = x.hi.floor();
+            digits.push(d as i32);
+            x = (x + -d) * 10.0;
+        }
+        if digits.pop().unwrap() >= 5 {
+            digits[DECIMAL_DIGITS - 1] += 1;
+        }
+        for i in (1..digits.len()).rev() {
+            if digits[i] < 0 {
+                digits[i] += 10;
+                digits[i - 1] -= 1;
+            } else if digits[i] > 9 {
+                digits[i] -= 10;
+                digits[i - 1] += 1;
+            }
+        }
+        if digits[0] > 9 {
+            digits[0] -= 10;
+            digits.insert(0, 1);
+            digits.pop();
+            exponent += 1;
+        }
+        while digits.len() > 1 && digits.last() == Some(&0) {
+            digits.pop();
+        }
+
+        let digits: Vec<char> = digits.iter().map(|&d| char::from(b'0' + d as u8)).collect();
+        let mut text = String::from(if negative { "-" } else { "" });
+        if (-5..0).contains(&exponent) {
+            text += "0.";
+            text.extend(std::iter::repeat_n('0', (-exponent - 1) as usize));
+            text.extend(&digits);
+        } else if (0..=5).contains(&exponent) {
+            let point = exponent as usize + 1;
+            text.extend(digits.iter().take(point));
+            text.extend(std::iter::repeat_n('0', point.saturating_sub(digits.len())));
+            if digits.len() > point {
+                text.push('.');
+                text.extend(&digits[point..]);
+            }
+        } else {
+            text.push(digits[0]);
+            if digits.len() > 1 {
+                text.push('.');
+                text.extend(&digits[1..]);
+            }
+            text += &format!("e{}", exponent);
+        }
+        text
+    }
+}
+
+// Decimal text like "-0.743643887037158704752191506114774" or "1.5e-3",
+// read digit by digit so nothing is lost to an f64 on the way.
+impl FromStr for Dd {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Dd, String> {
+        let invalid = || format!("invalid number '{}'", text);
+        let trimmed = text.trim();
+        let (negative, rest) = match trimmed.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+        };
+        let (mantissa, exponent) = match rest.find(['e', 'E']) {
+            Some(at) => (&rest[..at], rest[at + 1..].parse::<i32>().map_err(|_| invalid())?),
+            None => (rest, 0),
+        };
+        let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        if int.is_empty() && frac.is_empty() {
+            return Err(invalid());
+        }
+
+        let mut value = Dd::default();
+        for c in int.chars().chain(frac.chars()) {
+            let digit = c.to_digit(10).ok_or_else(invalid)?;
+            value = value * 10.0 + digit as f64;
+        }
+        let exponent = exponent - frac.len() as i32;
+        let scale = pow10(exponent.unsigned_abs());
+        let value = if exponent < 0 { value / scale } else { value * scale };
+        Ok(if negative { -value } else { value })
+    }
+}
+
+impl From<f64> for Dd {
+    fn from(hi: f64) -> Dd {
+        Dd { hi, lo: 0.0 }
+    }
+}
+
+impl Add for Dd {
+    type Output = Dd;
+
+    fn add(self, rhs: Dd) -> Dd {
+        let s = two_sum(self.hi, rhs.hi);
+        let t = two_sum(self.lo, rhs.lo);
+        let s = quick_two_sum(s.hi, s.lo + t.hi);
+        quick_two_sum(s.hi, s.lo + t.lo)
+    }
+}
+
+impl Add<f64> for Dd {
+    type Output = Dd;
+
+    fn add(self, rhs: f64) -> Dd {
+        let s = two_sum(self.hi, rhs);
+        quick_two_sum(s.hi, s.lo + self.lo)
+    }
+}
+
+impl Neg for Dd {
+    type Output = Dd;
+
+    fn neg(self) -> Dd {
+        Dd { hi: -self.hi, lo: -self.lo }
+    }
+}
+
+impl Sub for Dd {
+    type Output = Dd;
+
+    fn sub(self, rhs: Dd) -> Dd {
+        self + -rhs
+    }
+}
+
+impl Mul for Dd {
+    type Output = Dd;
+
+    fn mul(self, rhs: Dd) -> Dd {
+        let p = two_prod(self.hi, rhs.hi);
+        quick_two_sum(p.hi, p.lo + (self.hi * rhs.lo + self.lo * rhs.hi))
+    }
+}
+
+impl Div for Dd {
+    type Output = Dd;
+
+    // long division, each step takes the next 53 bits of the quotient
+    fn div(self, rhs: Dd) -> Dd {
+        let q1 = self.hi / rhs.hi;
+        let r = self - rhs * q1;
+        let q2 = r.hi / rhs.hi;
+        let r = r - rhs * q2;
+        let q3 = r.hi / rhs.hi;
+        quick_two_sum(q1, q2) + q3
+    }
+}
+
+impl Mul<f64> for Dd {
+    type Output = Dd;
+
+    fn mul(self, rhs: f64) -> Dd {
+        let p = two_prod(self.hi, rhs);
+        quick_two_sum(p.hi, p.lo + self.lo * rhs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Dd {
+        text.parse().unwrap()
+    }
+
+    // equal to within the precision of a Dd
+    fn assert_close(a: Dd, b: Dd) {
+        let diff = (a - b).to_f64().abs();
+        assert!(diff <= a.hi.abs() * 1e-30, "{:?} and {:?} differ by {:e}", a, b, diff);
+    }
+
+    #[test]
+    fn decimal_round_trip_keeps_every_digit() {
+        for text in ["-0.7436438870371587047521915061147", "0.1318259042053119704931320563851",
+                     "1.2345678901234567890123456789012e-20", "3.1415926535897932384626433832795e17", "0.1"] {
+            let value = parse(text);
+            // more digits than an f64 holds survive
+            assert!(value.lo != 0.0, "{} lost its low part", text);
+            assert_close(value, parse(&value.to_decimal()));
+        }
+    }
+
+    #[test]
+    fn decimal_notation() {
+        assert_eq!(parse("1.5e-3").to_decimal(), "0.0015");
+        assert_eq!(parse("-1.25E2").to_decimal(), "-125");
+        assert_eq!(parse(".5").to_decimal(), "0.5");
+        assert_eq!(parse("123456.5").to_decimal(), "123456.5");
+        assert_eq!(parse("1234567").to_decimal(), "1.234567e6");
+        assert_eq!(parse("0").to_decimal(), "0");
+    }
+
+    #[test]
+    fn rounding_carries_into_the_next_power_of_ten() {
+        assert_eq!(parse("9.99999999999999999999999999999999999").to_decimal(), "10");
+        assert_eq!(parse("-0.0999999999999999999999999999999999999").to_decimal(), "-0.1");
+    }
+
+    #[test]
+    fn invalid_numbers() {
+        for text in ["", "-", ".", "e5", "1e", "1.2.3", "abc", "1,5"] {
+            assert_eq!(text.parse::<Dd>(), Err(format!("invalid number '{}'", text)));
+        }
+    }
+}
@@ -1,17 +1,39 @@
 extern crate sdl2;
 
-use std::time::Instant;
+use std::time::Duration;
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels;
-use sdl2::rect::Point;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::{self, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture};
+use sdl2::video::Window;
 
-use crate::compute::mandelbrot;
-use crate::ocl3;
+use crate::backend::{RenderParams, Renderer};
+use crate::bookmark::{self, Bookmark, QUICK_SLOTS};
+use crate::device::DeviceFilter;
+use crate::palette::{Palette, PALETTE_NAMES};
+use crate::supersample::{self, Pattern, Sampling};
+use crate::viewport::Viewport;
+use crate::worker::{Frame, RenderWorker};
 
-pub(crate) fn main(res: u32, max_it: i32) -> Result<(), String> {
-    let screen_width: u32 = 3 * res;
-    let screen_height: u32 = 2 * res;
+// factor the pixel spacing is multiplied with per zoom step
+const ZOOM_STEP: f64 = 1.25;
+// fraction of the window moved per arrow key press
+const PAN_STEP: f64 = 0.125;
+// longest wait for a finished pass before events are polled again
+const FRAME_WAIT: Duration = Duration::from_millis(10);
+// largest supersampling factor the A key cycles through
+const MAX_SAMPLES: u32 = 4;
+// exponent used when cycling to the multibrot formula
+const MULTIBROT_POWER: f64 = 3.0;
+// palette offset change per bracket key press
+const PALETTE_SHIFT: f32 = 0.05;
+
+pub(crate) fn main(mut viewport: Viewport, mut params: RenderParams, renderer: Box<dyn Renderer>, palette: Palette, mut sampling: Sampling,
+            filter: DeviceFilter) -> Result<(), String> {
+    let screen_width: u32 = viewport.width;
+    let screen_height: u32 = viewport.height;
 
     let sdl_context = sdl2::init()?;
     let video_subsys = sdl_context.video()?;
@@ -32,11 +54,39 @@ pub(crate) fn main(res: u32, max_it: i32) -> Result<(), String> {
     canvas.clear();
     canvas.present();
 
-    let mut lastx = 0;
-    let mut lasty = 0;
+    // every pass is coloured into the top left of this texture and scaled
+    // up to the window when copied
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGBA32, screen_width, screen_height)
+        .map_err(|e| e.to_string())?;
+
+
+    let mut drag_start: Option<(i32, i32)> = None;
+    // the Mandelbrot view to return to from Julia mode, and the last c
+    let mut mandelbrot_view = viewport;
+    let mut last_julia: Option<((f64, f64), Viewport)> = None;
+    let mut mouse_pos: (i32, i32) = ((screen_width / 2) as i32, (screen_height / 2) as i32);
 
     let mut events = sdl_context.event_pump()?;
 
+    // the palette given at startup followed by the builtin ones
+    let mut palettes = vec![palette];
+    for name in PALETTE_NAMES {
+        if palettes[0].name != name {
+            palettes.push(Palette::builtin(name).unwrap());
+        }
+    }
+    let mut current = 0;
+
+    // what bookmarks record until the first frame names its backend
+    let initial_backend = renderer.name();
+    let worker = RenderWorker::spawn(renderer, filter);
+    // last finished pass, kept so palette changes only recolour it
+    let mut frame: Option<Frame> = None;
+
+    let mut dirty = true;
+    let mut recolour = false;
     'main: loop {
         for event in events.poll_iter() {
             match event {
@@ -44,105 +94,244 @@ pub(crate) fn main(res: u32, max_it: i32) -> Result<(), String> {
 
                 Event::KeyDown {
                     keycode: Some(keycode),
+                    keymod,
                     ..
-                } => unsafe {
+                } => {
                     if keycode == Keycode::Escape {
                         break 'main;
                     } else if keycode == Keycode::Num1 {
-                        let timer = Instant::now();
-                        let mut vec = vec![0; (screen_height * screen_width) as usize];
-                        let ret = mandelbrot(&mut vec, res, max_it);
-                        println!("{ret:?}");
-                        for i in 0..screen_width {
-                            for j in 0..screen_height {
-                                canvas.set_draw_color(pixels::Color::RGB(0, 0, ((vec.get((i * screen_height + j) as usize).unwrap().to_owned() as f32)/(max_it as f32) * 255.0) as u8));
-                                let _ = canvas.draw_point(Point::new(i as i32, j as i32));
-                            }
-                        }
-                        canvas.present();
-                        println!("took: {}", timer.elapsed().as_nanos())
+                        worker.switch_backend("ocl");
+                        dirty = true;
                     } else if keycode == Keycode::Num2 {
-                        let timer = Instant::now();
-                        let mut vec = vec![0; (screen_height * screen_width) as usize];
-                        let ret = ocl3::main(&mut vec, res, max_it);
-                        println!("{ret:?}");
-                        for i in 0..screen_width {
-                            for j in 0..screen_height {
-                                canvas.set_draw_color(pixels::Color::RGB(((vec.get((i * screen_height + j) as usize).unwrap().to_owned() as f32)/(max_it as f32) * 255.0) as u8, 0, 0));
-                                let _ = canvas.draw_point(Point::new(i as i32, j as i32));
-                            }
-                        }
-                        canvas.present();
-                        println!("took: {}", timer.elapsed().as_nanos())
+                        worker.switch_backend("opencl3");
+                        dirty = true;
+                    } else if keycode == Keycode::Num3 {
+                        worker.switch_backend("perturbation");
+                        dirty = true;
                     } else if keycode == Keycode::Space {
-                        let timer = Instant::now();
-                        for _i in 0..screen_width {
-                            for _j in 0..screen_height {
-                                let i = _i as f32;
-                                let j = _j as f32;
-                                let x0:f32 = i/(res as f32) - 2.0;
-                                let y0:f32 = j/(res as f32) - 1.0;
-                                let mut x:f32 = 0.0;
-                                let mut y:f32 = 0.0;
-                                let mut x2:f32 = 0.0;
-                                let mut y2:f32 = 0.0;
-                                let mut it = 0;
-
-                                //check if in main bulbs
-                                let q = (x0 - (1.0/4.0)).powf(2.0) + y0.powf(2.0);
-                                if q*(q + (x + (1.0/4.0))) < (1.0/4.0)*y0.powf(2.0) {
-                                    canvas.set_draw_color(pixels::Color::RGB(0, 255, 0));
-                                    let _ = canvas.draw_point(Point::new(_i as i32, _j as i32));
-                                    continue
-                                }
-
-                                //escape time algorithm
-                                while x.powf(2.0) + y.powf(2.0) <= 4.0 && it < max_it {
-                                    y = 2.0*x*y + y0;
-                                    x = x2 - y2 + x0;
-                                    x2 = x*x;
-                                    y2 = y*y;
-                                    it = it + 1;
+                        worker.switch_backend("cpu");
+                        dirty = true;
+                    } else if keycode == Keycode::Left {
+                        viewport.pan(-PAN_STEP * screen_width as f64, 0.0);
+                        dirty = true;
+                    } else if keycode == Keycode::Right {
+                        viewport.pan(PAN_STEP * screen_width as f64, 0.0);
+                        dirty = true;
+                    } else if keycode == Keycode::Up {
+                        viewport.pan(0.0, -PAN_STEP * screen_height as f64);
+                        dirty = true;
+                    } else if keycode == Keycode::Down {
+                        viewport.pan(0.0, PAN_STEP * screen_height as f64);
+                        dirty = true;
+                    } else if keycode == Keycode::PageUp {
+                        viewport.zoom(1.0 / ZOOM_STEP);
+                        dirty = true;
+                    } else if keycode == Keycode::PageDown {
+                        viewport.zoom(ZOOM_STEP);
+                        dirty = true;
+                    } else if keycode == Keycode::J {
+                        // toggle between the Mandelbrot set and the last Julia set
+                        match params.julia.take() {
+                            Some(c) => {
+                                last_julia = Some((c, viewport));
+                                viewport = mandelbrot_view;
+                            }
+                            None => {
+                                if let Some((c, view)) = last_julia {
+                                    mandelbrot_view = viewport;
+                                    params.julia = Some(c);
+                                    viewport = view;
                                 }
-                                let it = it as f32;
-                                canvas.set_draw_color(pixels::Color::RGB(0, (it/(max_it as f32) * 255.0) as u8, 0));
-                                let _ = canvas.draw_point(Point::new(i as i32, j as i32));
                             }
                         }
-                        canvas.present();
-                        println!("took {}", timer.elapsed().as_nanos())
-                    } else if keycode == Keycode::H {
-                        let timer = Instant::now();
-                        canvas.set_draw_color(pixels::Color::RGB(255, 0, 0));
-                        for _i in 0..screen_width {
-                            for _j in 0..screen_height {
-                                let x = (_i as f32)/(res as f32)*2.0 - 2.0;
-                                let y = (_j as f32)/(res as f32)*2.0 - 2.0;
-                                println!("{}: {}", x, y);
-                                if x.powf(2.0) + (y - x.powf(2.0 * (1.0/3.0))).powf(2.0) == 1.0 {
-                                    let _ = canvas.draw_point(Point::new(_i as i32, _j as i32));
+                        dirty = true;
+                    } else if keycode == Keycode::F {
+                        params.formula = params.formula.next(MULTIBROT_POWER);
+                        println!("formula: {:?}", params.formula);
+                        dirty = true;
+                    } else if keycode == Keycode::I {
+                        params.interior_check = !params.interior_check;
+                        println!("interior check: {}", params.interior_check);
+                        dirty = true;
+                    } else if keycode == Keycode::O {
+                        params.periodicity = !params.periodicity;
+                        println!("periodicity check: {}", params.periodicity);
+                        dirty = true;
+                    } else if keycode == Keycode::C {
+                        params.periods = !params.periods;
+                        println!("colour by period: {}", params.periods);
+                        dirty = true;
+                    } else if keycode == Keycode::A {
+                        sampling.samples = sampling.samples % MAX_SAMPLES + 1;
+                        println!("supersampling: {0}x{0}", sampling.samples);
+                        dirty = true;
+                    } else if keycode == Keycode::G {
+                        sampling.pattern = match sampling.pattern {
+                            Pattern::Grid => Pattern::Jittered,
+                            Pattern::Jittered => Pattern::Grid,
+                        };
+                        println!("sampling pattern: {:?}", sampling.pattern);
+                        dirty = sampling.is_enabled();
+                    } else if keycode == Keycode::S {
+                        params.smooth = !params.smooth;
+                        dirty = true;
+                    } else if keycode == Keycode::P {
+                        current = (current + 1) % palettes.len();
+                        println!("palette: {}", palettes[current].name);
+                        recolour = true;
+                    } else if keycode == Keycode::M {
+                        palettes[current].mapping = palettes[current].mapping.next();
+                        println!("mapping: {:?}", palettes[current].mapping);
+                        recolour = true;
+                    } else if keycode == Keycode::LeftBracket {
+                        palettes[current].offset -= PALETTE_SHIFT;
+                        recolour = true;
+                    } else if keycode == Keycode::RightBracket {
+                        palettes[current].offset += PALETTE_SHIFT;
+                        recolour = true;
+                    } else if keycode == Keycode::B {
+                        let backend = frame.as_ref().map_or(initial_backend, |f| f.backend);
+                        let bookmark = Bookmark::of_view(&viewport, &params, &palettes[current].source, backend);
+                        let path = bookmark::next_free_path();
+                        match bookmark.save(&path) {
+                            Ok(()) => println!("saved bookmark {}", path),
+                            Err(err) => println!("saving the bookmark failed: {}", err),
+                        }
+                    } else if let Some(slot) = quick_slot(keycode) {
+                        let path = bookmark::quick_path(slot);
+                        if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                            let backend = frame.as_ref().map_or(initial_backend, |f| f.backend);
+                            let bookmark = Bookmark::of_view(&viewport, &params, &palettes[current].source, backend);
+                            match bookmark.save(&path) {
+                                Ok(()) => println!("stored quick bookmark {}", slot),
+                                Err(err) => println!("storing quick bookmark {} failed: {}", slot, err),
+                            }
+                        } else {
+                            match Bookmark::load(&path) {
+                                Ok(bookmark) => {
+                                    viewport = bookmark.viewport(screen_width, screen_height);
+                                    params.max_iter = bookmark.max_iter;
+                                    params.formula = bookmark.formula;
+                                    params.julia = bookmark.julia;
+                                    if let Some(source) = &bookmark.palette {
+                                        current = select_palette(&mut palettes, source).unwrap_or(current);
+                                    }
+                                    if let Some(backend) = bookmark.backend {
+                                        worker.switch_backend(backend);
+                                    }
+                                    println!("recalled quick bookmark {}", slot);
+                                    dirty = true;
                                 }
+                                Err(err) => println!("quick bookmark {}: {}", slot, err),
                             }
                         }
-                        canvas.present();
-                        println!("took {}", timer.elapsed().as_nanos())
                     }
                 }
 
+                Event::MouseWheel { y, .. } => {
+                    // zoom around the cursor, the point under it stays in place
+                    viewport.zoom_at(mouse_pos.0 as f64, mouse_pos.1 as f64, ZOOM_STEP.powi(-y));
+                    dirty = true;
+                }
 
+                Event::MouseMotion { x, y, .. } => {
+                    mouse_pos = (x, y);
+                }
 
-                Event::MouseButtonDown { x, y, .. } => {
-                    let _ = canvas.draw_line(Point::new(lastx, lasty), Point::new(x, y));
-                    lastx = x;
-                    lasty = y;
-                    println!("mouse btn down at ({},{})", x, y);
-                    canvas.present();
+                Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } => {
+                    drag_start = Some((x, y));
+                }
+
+                Event::MouseButtonUp { mouse_btn: MouseButton::Left, x, y, .. } => {
+                    if let Some((sx, sy)) = drag_start.take() {
+                        if (sx, sy) != (x, y) {
+                            viewport.pan((sx - x) as f64, (sy - y) as f64);
+                            dirty = true;
+                        } else if params.julia.is_none() {
+                            // a click on the Mandelbrot set picks c for a Julia set
+                            let c = viewport.pixel_to_complex(x as f64, y as f64);
+                            println!("julia: c = {} {:+}i", c.0, c.1);
+                            mandelbrot_view = viewport;
+                            params.julia = Some(c);
+                            viewport = Viewport::julia_overview(screen_width, screen_height);
+                            dirty = true;
+                        }
+                    }
                 }
 
                 _ => {}
             }
         }
+
+        // any navigation restarts from the coarsest pass, the worker drops
+        // the rest of the stale view
+        if dirty {
+            worker.render(&viewport, &params, &sampling);
+            dirty = false;
+        }
+        // waiting here also keeps the loop from spinning while idle
+        if let Some(finished) = worker.latest(FRAME_WAIT) {
+            frame = Some(finished);
+            recolour = true;
+        }
+        if recolour {
+            if let Some(frame) = &frame {
+                if let Err(err) = present(&mut canvas, &mut texture, frame, &palettes[current]) {
+                    println!("presenting the frame failed: {}", err);
+                }
+            }
+            recolour = false;
+        }
+    }
+
+    Ok(())
+}
+
+// F1 to F9, the quick bookmark slots
+fn quick_slot(keycode: Keycode) -> Option<u32> {
+    let keys = [Keycode::F1, Keycode::F2, Keycode::F3, Keycode::F4, Keycode::F5, Keycode::F6, Keycode::F7, Keycode::F8, Keycode::F9];
+    keys.iter().position(|&k| k == keycode).map(|i| i as u32 + 1).filter(|&slot| slot <= QUICK_SLOTS)
+}
+
+// index of the palette with this source, loading it when it is not among
+// `palettes` yet
+fn select_palette(palettes: &mut Vec<Palette>, source: &str) -> Option<usize> {
+    if let Some(i) = palettes.iter().position(|p| p.source == source) {
+        return Some(i);
+    }
+    match Palette::by_name_or_file(source) {
+        Ok(palette) => {
+            palettes.push(palette);
+            Some(palettes.len() - 1)
+        }
+        Err(err) => {
+            println!("could not load palette: {}", err);
+            None
+        }
     }
+}
 
+// colours the frame into `texture` and copies it to the window, every frame
+// pixel covering a `step` x `step` block
+fn present(canvas: &mut Canvas<Window>, texture: &mut Texture, frame: &Frame, palette: &Palette) -> Result<(), String> {
+    let buffer = &frame.buffer;
+    let (width, height, step) = (buffer.width / frame.samples, buffer.height / frame.samples, frame.step);
+    let area = Rect::new(0, 0, width, height);
+    if frame.samples == 1 {
+        texture.with_lock(area, |out, pitch| palette.colorize_rgba(buffer, frame.max_iter, out, pitch))?;
+    } else {
+        let colours = palette.colorize(buffer, frame.max_iter);
+        let colours = supersample::downsample(&colours, buffer.width, buffer.height, frame.samples);
+        texture.with_lock(area, |out, pitch| {
+            for (j, row) in colours.chunks(width as usize).enumerate() {
+                for (i, c) in row.iter().enumerate() {
+                    let at = j * pitch + i * 4;
+                    out[at..at + 4].copy_from_slice(&[c.0, c.1, c.2, 255]);
+                }
+            }
+        })?;
+    }
+    canvas.copy(texture, area, Rect::new(0, 0, width * step, height * step))?;
+    canvas.present();
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,208 @@
+// OpenCL device enumeration and selection.
+//
+// Devices are addressed as platform index plus device index within that
+// platform (counting devices of every type), the same numbering both the
+// ocl and opencl3 backends see, so a selection made here can be opened by
+// either of them.
+
+use opencl3::device::{Device, CL_DEVICE_TYPE_ALL, CL_DEVICE_TYPE_CPU, CL_DEVICE_TYPE_GPU};
+use opencl3::error_codes::{CL_DEVICE_NOT_FOUND, CL_PLATFORM_NOT_FOUND_KHR};
+use opencl3::platform::get_platforms;
+use opencl3::types::cl_device_type;
+
+use crate::ocl3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum DeviceType {
+    Gpu,
+    Cpu,
+    All,
+}
+
+impl DeviceType {
+    pub(crate) fn from_name(name: &str) -> Result<DeviceType, String> {
+        match name {
+            "gpu" => Ok(DeviceType::Gpu),
+            "cpu" => Ok(DeviceType::Cpu),
+            "all" => Ok(DeviceType::All),
+            _ => Err(format!("unknown device type '{}', expected gpu, cpu or all", name)),
+        }
+    }
+
+    fn matches(self, flags: cl_device_type) -> bool {
+        match self {
+            DeviceType::Gpu => flags & CL_DEVICE_TYPE_GPU != 0,
+            DeviceType::Cpu => flags & CL_DEVICE_TYPE_CPU != 0,
+            DeviceType::All => true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct DeviceFilter {
+    pub(crate) platform: Option<usize>,
+    // device index within the platform, or a case-insensitive name fragment
+    pub(crate) device: Option<String>,
+    // None unless --device-type was given, see device_type()
+    pub(crate) kind: Option<DeviceType>,
+}
+
+impl DeviceFilter {
+    // whether the user asked for a particular device
+    pub(crate) fn is_explicit(&self) -> bool {
+        self.platform.is_some() || self.device.is_some()
+    }
+
+    // GPUs by default, any type once a platform or device was named
+    pub(crate) fn device_type(&self) -> DeviceType {
+        match self.kind {
+            Some(kind) => kind,
+            None if self.is_explicit() => DeviceType::All,
+            None => DeviceType::Gpu,
+        }
+    }
+
+    fn describe(&self) -> String {
+        let mut text = format!("type {:?}", self.device_type()).to_lowercase();
+        if let Some(platform) = self.platform {
+            text += &format!(", platform {}", platform);
+        }
+        if let Some(device) = &self.device {
+            text += &format!(", device '{}'", device);
+        }
+        text
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct DeviceEntry {
+    pub(crate) platform: usize,
+    pub(crate) index: usize,
+    pub(crate) platform_name: String,
+    pub(crate) name: String,
+    pub(crate) vendor: String,
+    pub(crate) kind: &'static str,
+    pub(crate) fp64: bool,
+    pub(crate) compute_units: u32,
+    pub(crate) max_alloc: u64,
+}
+
+impl DeviceEntry {
+    fn matches(&self, filter: &DeviceFilter, flags: cl_device_type) -> bool {
+        if filter.platform.is_some_and(|p| p != self.platform) {
+            return false;
+        }
+        if let Some(device) = &filter.device {
+            let by_index = device.parse::<usize>().is_ok_and(|i| i == self.index);
+            let by_name = self.name.to_lowercase().contains(&device.to_lowercase());
+            if !by_index && !by_name {
+                return false;
+            }
+        }
+        filter.device_type().matches(flags)
+    }
+}
+
+fn kind_name(flags: cl_device_type) -> &'static str {
+    if flags & CL_DEVICE_TYPE_GPU != 0 {
+        "gpu"
+    } else if flags & CL_DEVICE_TYPE_CPU != 0 {
+        "cpu"
+    } else {
+        "other"
+    }
+}
+
+// A missing OpenCL runtime or a platform without devices is reported by the
+// ICD loader as an error, both just mean there is nothing to list.
+fn list_with_flags() -> opencl3::Result<Vec<(DeviceEntry, cl_device_type)>> {
+    let mut entries = Vec::new();
+    let platforms = match get_platforms() {
+        Err(e) if e.0 == CL_PLATFORM_NOT_FOUND_KHR => Vec::new(),
+        ret => ret?,
+    };
+    for (p_idx, platform) in platforms.iter().enumerate() {
+        let platform_name = platform.name()?;
+        let ids = match platform.get_devices(CL_DEVICE_TYPE_ALL) {
+            Err(e) if e.0 == CL_DEVICE_NOT_FOUND => Vec::new(),
+            ret => ret?,
+        };
+        for (d_idx, id) in ids.into_iter().enumerate() {
+            let device = Device::new(id);
+            let flags = device.dev_type()?;
+            entries.push((DeviceEntry {
+                platform: p_idx,
+                index: d_idx,
+                platform_name: platform_name.clone(),
+                name: device.name()?,
+                vendor: device.vendor()?,
+                kind: kind_name(flags),
+                fp64: ocl3::supports_fp64(&device)?,
+                compute_units: device.max_compute_units()?,
+                max_alloc: device.max_mem_alloc_size()?,
+            }, flags));
+        }
+    }
+    Ok(entries)
+}
+
+pub(crate) fn list() -> Result<Vec<DeviceEntry>, String> {
+    Ok(list_with_flags().map_err(|e| e.to_string())?.into_iter().map(|(entry, _)| entry).collect())
+}
+
+// first device matching the filter
+pub(crate) fn select(filter: &DeviceFilter) -> Result<DeviceEntry, String> {
+    let entries = list_with_flags().map_err(|e| format!("listing OpenCL devices failed: {}", e))?;
+    if entries.is_empty() {
+        return Err("no OpenCL platform or device found".to_string());
+    }
+    entries
+        .into_iter()
+        .find(|(entry, flags)| entry.matches(filter, *flags))
+        .map(|(entry, _)| entry)
+        .ok_or_else(|| format!("no OpenCL device matches {}", filter.describe()))
+}
+
+// Listing grouped by platform with the indices accepted by --platform and
+// --device.
+pub(crate) fn to_text(entries: &[DeviceEntry]) -> String {
+    if entries.is_empty() {
+        return "no OpenCL platform or device found\n".to_string();
+    }
+    let mut text = String::new();
+    for (n, e) in entries.iter().enumerate() {
+        if n == 0 || entries[n - 1].platform != e.platform {
+            text += &format!("[{}] {}\n", e.platform, e.platform_name);
+        }
+        text += &format!("    [{}.{}] {} ({}, {}, fp64 {}, {} compute units, {} MiB max allocation)\n",
+            e.platform, e.index, e.name, e.kind, e.vendor, if e.fp64 { "yes" } else { "no" }, e.compute_units,
+            e.max_alloc / (1024 * 1024));
+    }
+    text
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+pub(crate) fn to_json(entries: &[DeviceEntry]) -> String {
+    if entries.is_empty() {
+        return "[]".to_string();
+    }
+    let items: Vec<String> = entries.iter().map(|e| format!(
+        "  {{\"platform\": {}, \"device\": {}, \"platform_name\": {}, \"name\": {}, \"vendor\": {}, \"type\": {}, \"fp64\": {}, \"compute_units\": {}, \"max_alloc\": {}}}",
+        e.platform, e.index, json_string(&e.platform_name), json_string(&e.name), json_string(&e.vendor),
+        json_string(e.kind), e.fp64, e.compute_units, e.max_alloc,
+    )).collect();
+    format!("[\n{}\n]", items.join(",\n"))
+}
@@ -0,0 +1,67 @@
+use std::time::Instant;
+
+use crate::backend::{RenderParams, Renderer};
+use crate::image::ImageWriter;
+use crate::palette::{self, Mapping, Palette, Rgb};
+use crate::supersample::Sampling;
+use crate::viewport::Viewport;
+
+// upper bound on the pixels of one tile, keeps host memory bounded for
+// backends without a device limit
+const MAX_TILE_PIXELS: usize = 1 << 24;
+// pixels of the preview the histogram of a tiled render is taken from
+const HISTOGRAM_PIXELS: usize = 1 << 20;
+
+// Renders one frame straight to an image file, no window or display needed.
+pub(crate) fn render(viewport: &Viewport, params: &RenderParams, sampling: &Sampling, renderer: &mut dyn Renderer,
+                     palette: &Palette, path: &str) -> Result<(), String> {
+    let timer = Instant::now();
+    let mut writer = ImageWriter::create(path, viewport.width, viewport.height)?;
+    let tiles = render_bands(viewport, params, sampling, renderer, palette, &mut |pixels| writer.write_rows(pixels))?;
+    writer.finish()?;
+
+    println!("{} wrote {} ({}x{}, {} tiles, {} samples per pixel) in {} ms", renderer.name(), path, viewport.width,
+        viewport.height, tiles, sampling.samples * sampling.samples, timer.elapsed().as_millis());
+    Ok(())
+}
+
+// Renders and colours one frame, handing it to `write` top to bottom.
+//
+// The image is split into bands of whole rows, each small enough for the
+// backend's largest buffer, and every band is passed on before the next one
+// is rendered, so neither the device nor the host ever holds the full frame.
+// Returns the number of bands.
+pub(crate) fn render_bands(viewport: &Viewport, params: &RenderParams, sampling: &Sampling, renderer: &mut dyn Renderer,
+                           palette: &Palette, write: &mut dyn FnMut(&[Rgb]) -> Result<(), String>) -> Result<u32, String> {
+    let max_pixels = renderer.max_pixels().unwrap_or(MAX_TILE_PIXELS).min(MAX_TILE_PIXELS);
+    // samples of one output row
+    let row_samples = (sampling.samples as usize).checked_mul(sampling.samples as usize)
+        .and_then(|samples| samples.checked_mul(viewport.width as usize))
+        .ok_or_else(|| format!("{0}x{0} samples per pixel of {1} pixels per row are too many", sampling.samples, viewport.width))?;
+    if row_samples > max_pixels {
+        return Err(format!("{} samples per row exceed the {} backend's limit of {} pixels",
+            row_samples, renderer.name(), max_pixels));
+    }
+    let band = (max_pixels / row_samples).min(viewport.height as usize) as u32;
+
+    // histogram mapping needs counts of the whole image, a single band has
+    // its own, otherwise they come from a preview at reduced resolution
+    let histogram = match palette.mapping {
+        Mapping::Histogram if band < viewport.height => {
+            let factor = ((viewport.len() / HISTOGRAM_PIXELS) as f64).sqrt().ceil().max(1.0) as u32;
+            let preview = renderer.render(&viewport.coarse(factor), params)?;
+            Some(palette::cumulative_histogram(&preview, params.max_iter))
+        }
+        _ => None,
+    };
+
+    let mut j = 0;
+    while j < viewport.height {
+        let rows = band.min(viewport.height - j);
+        let tile = viewport.tile(0, j, viewport.width, rows);
+        let pixels = sampling.render(renderer, &tile, params, palette, histogram.as_deref())?;
+        write(&pixels)?;
+        j += rows;
+    }
+    Ok(viewport.height.div_ceil(band))
+}
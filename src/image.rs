@@ -0,0 +1,145 @@
+// Image and video file output.
+//
+// Rows are streamed to the file top to bottom, so callers never need the
+// whole image in memory. PNG needs the `png` feature (on by default), binary
+// PPM is always available. Animations can also go to a single Y4M stream.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::palette::Rgb;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Format {
+    Png,
+    Ppm,
+}
+
+impl Format {
+    // picks the format from the file extension
+    pub(crate) fn for_path(path: &str) -> Result<Format, String> {
+        let lower = path.to_lowercase();
+        if lower.ends_with(".ppm") {
+            Ok(Format::Ppm)
+        } else if lower.ends_with(".png") {
+            if cfg!(feature = "png") {
+                Ok(Format::Png)
+            } else {
+                Err(format!("{}: built without the png feature, write a .ppm file instead", path))
+            }
+        } else {
+            Err(format!("{}: unknown image format, expected .png or .ppm", path))
+        }
+    }
+}
+
+enum Sink {
+    #[cfg(feature = "png")]
+    Png(Box<png::StreamWriter<'static, BufWriter<File>>>),
+    Ppm(BufWriter<File>),
+}
+
+pub(crate) struct ImageWriter {
+    path: String,
+    width: u32,
+    rows_left: u32,
+    sink: Sink,
+}
+
+impl ImageWriter {
+    pub(crate) fn create(path: &str, width: u32, height: u32) -> Result<ImageWriter, String> {
+        let format = Format::for_path(path)?;
+        let err = |e: &dyn std::fmt::Display| format!("{}: {}", path, e);
+        let mut file = BufWriter::new(File::create(path).map_err(|e| err(&e))?);
+        let sink = match format {
+            #[cfg(feature = "png")]
+            Format::Png => {
+                let mut encoder = png::Encoder::new(file, width, height);
+                encoder.set_color(png::ColorType::Rgb);
+                encoder.set_depth(png::BitDepth::Eight);
+                let writer = encoder.write_header().map_err(|e| err(&e))?;
+                Sink::Png(Box::new(writer.into_stream_writer().map_err(|e| err(&e))?))
+            }
+            #[cfg(not(feature = "png"))]
+            Format::Png => unreachable!(),
+            Format::Ppm => {
+                write!(file, "P6\n{} {}\n255\n", width, height).map_err(|e| err(&e))?;
+                Sink::Ppm(file)
+            }
+        };
+        Ok(ImageWriter { path: path.to_string(), width, rows_left: height, sink })
+    }
+
+    // appends whole rows, `pixels.len()` must be a multiple of the width
+    pub(crate) fn write_rows(&mut self, pixels: &[Rgb]) -> Result<(), String> {
+        let rows = (pixels.len() / self.width as usize) as u32;
+        if !pixels.len().is_multiple_of(self.width as usize) || rows > self.rows_left {
+            return Err(format!("{}: row data does not fit the image", self.path));
+        }
+        let bytes: Vec<u8> = pixels.iter().flat_map(|c| [c.0, c.1, c.2]).collect();
+        let ret = match &mut self.sink {
+            #[cfg(feature = "png")]
+            Sink::Png(writer) => writer.write_all(&bytes),
+            Sink::Ppm(writer) => writer.write_all(&bytes),
+        };
+        ret.map_err(|e| format!("{}: {}", self.path, e))?;
+        self.rows_left -= rows;
+        Ok(())
+    }
+
+    pub(crate) fn finish(self) -> Result<(), String> {
+        if self.rows_left != 0 {
+            return Err(format!("{}: {} rows missing", self.path, self.rows_left));
+        }
+        let path = self.path;
+        let ret = match self.sink {
+            #[cfg(feature = "png")]
+            Sink::Png(writer) => writer.finish().map_err(|e| e.to_string()),
+            Sink::Ppm(mut writer) => writer.flush().map_err(|e| e.to_string()),
+        };
+        ret.map_err(|e| format!("{}: {}", path, e))
+    }
+}
+
+// Uncompressed YUV4MPEG2 video, which most encoders read directly. Chroma is
+// kept at full resolution (C444), colours are converted with BT.601 to
+// limited range.
+pub(crate) struct Y4mWriter {
+    path: String,
+    width: u32,
+    height: u32,
+    file: BufWriter<File>,
+}
+
+impl Y4mWriter {
+    pub(crate) fn create(path: &str, width: u32, height: u32, fps: u32) -> Result<Y4mWriter, String> {
+        let err = |e: &dyn std::fmt::Display| format!("{}: {}", path, e);
+        let mut file = BufWriter::new(File::create(path).map_err(|e| err(&e))?);
+        writeln!(file, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444 XCOLORRANGE=LIMITED", width, height, fps).map_err(|e| err(&e))?;
+        Ok(Y4mWriter { path: path.to_string(), width, height, file })
+    }
+
+    // one frame, row by row
+    pub(crate) fn write_frame(&mut self, pixels: &[Rgb]) -> Result<(), String> {
+        if pixels.len() != self.width as usize * self.height as usize {
+            return Err(format!("{}: frame does not fit the video size", self.path));
+        }
+        let channel = |c: u8| c as f32 / 255.0;
+        let mut planes = vec![0u8; pixels.len() * 3];
+        let (y, chroma) = planes.split_at_mut(pixels.len());
+        let (u, v) = chroma.split_at_mut(pixels.len());
+        for (k, c) in pixels.iter().enumerate() {
+            let (r, g, b) = (channel(c.0), channel(c.1), channel(c.2));
+            y[k] = (16.0 + 65.481 * r + 128.553 * g + 24.966 * b).round() as u8;
+            u[k] = (128.0 - 37.797 * r - 74.203 * g + 112.0 * b).round() as u8;
+            v[k] = (128.0 + 112.0 * r - 93.786 * g - 18.214 * b).round() as u8;
+        }
+        self.file.write_all(b"FRAME\n")
+            .and_then(|_| self.file.write_all(&planes))
+            .map_err(|e| format!("{}: {}", self.path, e))
+    }
+
+    pub(crate) fn finish(mut self) -> Result<(), String> {
+        self.file.flush().map_err(|e| format!("{}: {}", self.path, e))
+    }
+}
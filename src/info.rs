@@ -11,7 +11,7 @@
 
 extern crate ocl;
 
-use ocl::core::{OclPrm, ProgramInfo};
+use ocl::core::{DeviceInfo, OclPrm, ProgramInfo};
 use ocl::{
     Buffer, Context, Device, Event, EventList, Image, Kernel, Platform, Program, Queue,
     Result as OclResult, Sampler,
@@ -29,9 +29,15 @@ static SRC: &'static str = r#"
     }
 "#;
 
+// Like Platform::list but returns an error instead of panicking when there
+// is no OpenCL runtime.
+pub(crate) fn platforms() -> OclResult<Vec<Platform>> {
+    Ok(ocl::core::get_platform_ids()?.into_iter().map(Platform::new).collect())
+}
+
 pub(crate) fn info() -> OclResult<()> {
     let dims = 2048;
-    let platforms = Platform::list();
+    let platforms = platforms()?;
 
     println!("Looping through avaliable platforms ({}):", platforms.len());
 
@@ -130,12 +136,17 @@ fn print_device_info(device: &Device) -> OclResult<()> {
         if !PRINT_DETAILED {
             print!("{t}", t = TAB);
         }
-        println!("Device (terse) {{ Name: {}, Vendor: {} }}", device.name()?,
-            device.vendor()?);
+        println!("Device (terse) {{ Name: {}, Vendor: {}, fp64: {} }}", device.name()?,
+            device.vendor()?, supports_fp64(device)?);
     }
     Ok(())
 }
 
+// whether the device can run the double precision kernel
+pub(crate) fn supports_fp64(device: &Device) -> OclResult<bool> {
+    Ok(device.info(DeviceInfo::Extensions)?.to_string().contains("cl_khr_fp64"))
+}
+
 fn print_context_info(context: &Context) {
     println!("{}", context);
 }
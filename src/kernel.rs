@@ -0,0 +1,306 @@
+// OpenCL program shared by the ocl and opencl3 backends.
+//
+// The kernel runs on a 2D range of width x height work items and derives
+// the complex coordinate of its pixel from the viewport origin and spacing,
+// so no coordinate buffers have to be uploaded.
+//
+// Besides the integer escape count every kernel writes a fractional count
+// to SMOOTH, which is only meaningful with a large bailout (squared escape
+// radius), see backend::SMOOTH_BAILOUT.
+//
+// With `julia` set the pixel is the starting point z0 and (cx, cy) the
+// constant c of the Julia set, otherwise z0 = 0 and the pixel is c.
+//
+// `formula` and `power` select the iteration z -> f(z) + c, see
+// backend::Formula for the numbering. With `interior_check` set, points in
+// the main cardioid or the period-2 bulb are reported as inside without
+// iterating, which only holds for the plain Mandelbrot set.
+//
+// With `periodicity` set the orbit is checked for cycles (Brent's method),
+// a point that comes back within 1/1024 of a pixel of an earlier one is
+// inside and stops early. PERIOD gets the cycle length, or 0 when none was
+// found.
+//
+// With `jitter` set every pixel is sampled at a pseudo-random point within
+// half a pixel of its usual position, used for stratified supersampling.
+//
+// `real` is float by default, building with DOUBLE_OPTIONS switches the
+// whole kernel to double, which needs a device with cl_khr_fp64.
+
+// Definitions shared by both programs, built in front of PROGRAM_SOURCE and
+// PERTURBATION_SOURCE.
+pub(crate) const PRELUDE: &str = r#"
+        #ifdef USE_DOUBLE
+        #pragma OPENCL EXTENSION cl_khr_fp64 : enable
+        typedef double real;
+        #else
+        typedef float real;
+        #endif
+
+        // it + 1 - log_d(log|z|), continuous across the iteration bands of a
+        // degree d formula
+        float smooth_count(int it, int iter, real log_z, real degree) {
+            if (it >= iter) {
+                return iter;
+            }
+            return it + 1 - log2((float) log_z) / log2((float) degree);
+        }
+
+        // log|z| of the escaped point from its squared magnitude, or when
+        // that overflowed from the one before, |z| ~ |z_prev|^power
+        real escaped_log(real mag, real prev, real power) {
+            return isfinite(mag) ? log(mag) / 2 : power * log(prev) / 2;
+        }
+
+        // main cardioid and period-2 bulb, both lie entirely inside the set,
+        // returns the period of the component or 0 outside of both
+        int in_main_bulbs(real x, real y) {
+            real xq = x - (real) 0.25;
+            real q = xq*xq + y*y;
+            if (q*(q + xq) <= (real) 0.25 * y*y) {
+                return 1;
+            }
+            return (x + 1)*(x + 1) + y*y <= (real) 0.0625 ? 2 : 0;
+        }
+
+        // offset in [-0.5, 0.5)^2 for pixel (i, j), the same as
+        // supersample::jitter on the host
+        uint mix_bits(uint x) {
+            x ^= x >> 16;
+            x *= 0x7feb352dU;
+            x ^= x >> 15;
+            x *= 0x846ca68bU;
+            x ^= x >> 16;
+            return x;
+        }
+
+        float2 jitter_offset(int i, int j) {
+            uint h = mix_bits((uint) i + mix_bits((uint) j));
+            return (float2) ((h & 0xffff) / 65536.0f - 0.5f, (h >> 16) / 65536.0f - 0.5f);
+        }
+    "#;
+
+pub(crate) const PROGRAM_SOURCE: &str = r#"
+        // z -> f(z) + c for the selected formula
+        void apply_formula(int formula, real power, real* x, real* y, real cx, real cy) {
+            real zx = *x;
+            real zy = *y;
+            real nx;
+            real ny;
+            switch (formula) {
+            case 1:
+                // multibrot, exact products for small integer powers
+                if (power == floor(power) && power >= 2 && power <= 64) {
+                    nx = zx;
+                    ny = zy;
+                    for (int k = 1; k < (int) power; k++) {
+                        real t = nx*zx - ny*zy;
+                        ny = nx*zy + ny*zx;
+                        nx = t;
+                    }
+                } else if (zx == 0 && zy == 0) {
+                    nx = 0;
+                    ny = 0;
+                } else {
+                    real r = pow(zx*zx + zy*zy, power / 2);
+                    real theta = atan2(zy, zx) * power;
+                    nx = r * cos(theta);
+                    ny = r * sin(theta);
+                }
+                break;
+            case 2:
+                // burning ship
+                zx = fabs(zx);
+                zy = fabs(zy);
+                nx = zx*zx - zy*zy;
+                ny = 2*zx*zy;
+                break;
+            case 3:
+                // tricorn, conjugate before squaring
+                nx = zx*zx - zy*zy;
+                ny = -2*zx*zy;
+                break;
+            case 4:
+                // celtic
+                nx = fabs(zx*zx - zy*zy);
+                ny = 2*zx*zy;
+                break;
+            default:
+                nx = zx*zx - zy*zy;
+                ny = 2*zx*zy;
+                break;
+            }
+            *x = nx + cx;
+            *y = ny + cy;
+        }
+
+        __kernel void mandelbrot(real x_min, real y_min, real step, __global int* RET, __global float* SMOOTH,
+                                 int iter, real bailout, int julia, real cx, real cy, int formula, real power,
+                                 int interior_check, __global int* PERIOD, int periodicity, int jitter) {
+            int i = get_global_id(0);
+            int j = get_global_id(1);
+            int id = j * get_global_size(0) + i;
+            float2 offset = jitter ? jitter_offset(i, j) : (float2) (0, 0);
+            real x0 = x_min + (i + offset.x) * step;
+            real y0 = y_min + (j + offset.y) * step;
+            real x = 0;
+            real y = 0;
+            if (julia) {
+                x = x0;
+                y = y0;
+                x0 = cx;
+                y0 = cy;
+            }
+            real exponent = formula == 1 ? power : 2;
+            real degree = fabs(exponent);
+            int it = 0;
+
+            int period = interior_check && !julia && formula == 0 ? in_main_bulbs(x0, y0) : 0;
+            if (period) {
+                RET[id] = iter;
+                SMOOTH[id] = iter;
+                PERIOD[id] = period;
+                return;
+            }
+
+            // Brent: compare against a saved orbit point that is replaced
+            // after 1, 2, 4, ... steps
+            real tolerance = step / 1024;
+            real sx = x;
+            real sy = y;
+            int steps = 0;
+            int limit = 1;
+            real prev = 0;
+            while (x*x + y*y <= bailout && it < iter) {
+                prev = x*x + y*y;
+                apply_formula(formula, power, &x, &y, x0, y0);
+                it = it + 1;
+                if (periodicity) {
+                    steps = steps + 1;
+                    if (fabs(x - sx) < tolerance && fabs(y - sy) < tolerance) {
+                        period = steps;
+                        it = iter;
+                        break;
+                    }
+                    if (steps == limit) {
+                        sx = x;
+                        sy = y;
+                        steps = 0;
+                        limit = limit * 2;
+                    }
+                }
+            }
+
+            RET[id] = it;
+            PERIOD[id] = period;
+            SMOOTH[id] = degree > 1 ? smooth_count(it, iter, escaped_log(x*x + y*y, prev, exponent), degree) : it;
+        }
+    "#;
+
+pub(crate) const KERNEL_NAME: &str = "mandelbrot";
+
+pub(crate) const DOUBLE_OPTIONS: &str = "-D USE_DOUBLE";
+
+pub(crate) fn fp64_unavailable(device_name: &str) -> String {
+    format!("device '{}' does not support cl_khr_fp64, double precision is unavailable", device_name)
+}
+
+// Perturbation kernel for deep zooms. Every pixel iterates only its offset
+// d from a reference orbit Z computed on the host in higher precision:
+//
+//     d' = 2 Z d + d^2 + dc
+//
+// When |Z + d - Z_0| drops below |d| the delta has stopped being small
+// compared to the orbit (a glitch), so the pixel rebases: d becomes
+// Z + d - Z_0 and restarts from the beginning of the reference orbit. The
+// same happens when the reference orbit runs out before the pixel escapes.
+// Z_0 is 0 for the Mandelbrot set.
+//
+// For Julia sets c is fixed, so dc = 0 and the delta instead starts at the
+// pixel's offset from the reference starting point Z_0, the view center.
+pub(crate) const PERTURBATION_SOURCE: &str = r#"
+        __kernel void perturbation(__global real* REF_X, __global real* REF_Y, int ref_len,
+                                   real dx_min, real dy_min, real step, __global int* RET, __global float* SMOOTH,
+                                   int iter, real bailout, int julia, int interior_check, real ref_cx, real ref_cy,
+                                   __global int* PERIOD, int periodicity, int jitter) {
+            int i = get_global_id(0);
+            int j = get_global_id(1);
+            int id = j * get_global_size(0) + i;
+            float2 offset = jitter ? jitter_offset(i, j) : (float2) (0, 0);
+            real dcx = dx_min + (i + offset.x) * step;
+            real dcy = dy_min + (j + offset.y) * step;
+            real dx = 0;
+            real dy = 0;
+            if (julia) {
+                dx = dcx;
+                dy = dcy;
+                dcx = 0;
+                dcy = 0;
+            }
+            int period = interior_check && !julia ? in_main_bulbs(ref_cx + dcx, ref_cy + dcy) : 0;
+            if (period) {
+                RET[id] = iter;
+                SMOOTH[id] = iter;
+                PERIOD[id] = period;
+                return;
+            }
+            real mag = 0;
+            int n = 0;
+            int it = 0;
+
+            // Brent cycle detection on the full orbit Z + d, the host turns it
+            // off once the tolerance is below the rounding error of Z
+            real tolerance = step / 1024;
+            real sx = REF_X[0] + dx;
+            real sy = REF_Y[0] + dy;
+            int steps = 0;
+            int limit = 1;
+
+            while (it < iter) {
+                real zx = REF_X[n];
+                real zy = REF_Y[n];
+                real ndx = 2*(zx*dx - zy*dy) + dx*dx - dy*dy + dcx;
+                real ndy = 2*(zx*dy + zy*dx) + 2*dx*dy + dcy;
+                dx = ndx;
+                dy = ndy;
+                n = n + 1;
+                it = it + 1;
+
+                real fx = REF_X[n] + dx;
+                real fy = REF_Y[n] + dy;
+                mag = fx*fx + fy*fy;
+                if (mag > bailout) {
+                    break;
+                }
+
+                if (periodicity) {
+                    steps = steps + 1;
+                    if (fabs(fx - sx) < tolerance && fabs(fy - sy) < tolerance) {
+                        period = steps;
+                        it = iter;
+                        break;
+                    }
+                    if (steps == limit) {
+                        sx = fx;
+                        sy = fy;
+                        steps = 0;
+                        limit = limit * 2;
+                    }
+                }
+
+                real rx = fx - REF_X[0];
+                real ry = fy - REF_Y[0];
+                if (rx*rx + ry*ry < dx*dx + dy*dy || n == ref_len - 1) {
+                    dx = rx;
+                    dy = ry;
+                    n = 0;
+                }
+            }
+
+            RET[id] = it;
+            SMOOTH[id] = smooth_count(it, iter, log(mag) / 2, 2);
+            PERIOD[id] = period;
+        }
+    "#;
+
+pub(crate) const PERTURBATION_KERNEL_NAME: &str = "perturbation";
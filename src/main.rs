@@ -1,14 +1,73 @@
 use std::env;
+use std::process;
 
+mod animation;
+mod backend;
+mod bench;
+mod bookmark;
+mod check;
+mod cli;
 mod demo;
 mod compute;
+mod cpu;
+mod dd;
+mod device;
+mod headless;
+mod image;
 mod info;
+mod kernel;
 mod ocl3;
+mod palette;
+mod perturbation;
+mod supersample;
+mod viewport;
+mod worker;
+
+use cli::Command;
+use palette::Palette;
+
+fn run(command: Command) -> Result<(), String> {
+    match command {
+        Command::View(options) => {
+            let (renderer, filter) = backend::open_with_fallback(options.backend_or("opencl3"), &options.device)?;
+            let palette = Palette::by_name_or_file(&options.palette)?;
+            demo::main(options.viewport(), options.params(), renderer, palette, options.sampling, filter)
+        }
+        Command::Render(options) => {
+            let (mut renderer, _) = backend::open_with_fallback(options.backend_or("cpu"), &options.device)?;
+            let palette = Palette::by_name_or_file(&options.palette)?;
+            headless::render(&options.viewport(), &options.params(), &options.sampling, renderer.as_mut(), &palette, &options.output)
+        }
+        // the ocl dump waits for platforms that never come without a runtime
+        Command::Devices(options) if options.detailed && device::list()?.is_empty() => {
+            print!("{}", device::to_text(&[]));
+            Ok(())
+        }
+        Command::Devices(options) if options.detailed => info::info().map_err(|e| e.to_string()),
+        Command::Devices(options) if options.json => {
+            println!("{}", device::to_json(&device::list()?));
+            Ok(())
+        }
+        Command::Devices(_) => {
+            print!("{}", device::to_text(&device::list()?));
+            Ok(())
+        }
+        Command::Bench(options) => bench::run(&options),
+        Command::Check(options) => check::run(&options),
+        Command::Animate(options) => animation::run(&options),
+        Command::Help => {
+            print!("{}", cli::USAGE);
+            Ok(())
+        }
+    }
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    println!("{args:?}");
-    let ret = demo::main(args.get(1).unwrap_or(&"100".to_string()).parse::<u32>().unwrap(),
-                         args.get(2).unwrap_or(&"1000".to_string()).parse::<i32>().unwrap());
-    println!("{ret:?}")
+    let args: Vec<String> = env::args().skip(1).collect();
+    let ret = cli::parse(&args).and_then(run);
+    if let Err(err) = ret {
+        eprintln!("error: {}", err);
+        eprintln!("run 'mandelbrot help' for usage");
+        process::exit(1);
+    }
 }
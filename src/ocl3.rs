@@ -15,130 +15,236 @@
 
 use opencl3::command_queue::{CommandQueue, CL_QUEUE_PROFILING_ENABLE};
 use opencl3::context::Context;
-use opencl3::device::{get_all_devices, Device, CL_DEVICE_TYPE_GPU};
+use opencl3::device::{Device, CL_DEVICE_TYPE_ALL};
+use opencl3::event::Event;
 use opencl3::kernel::{ExecuteKernel, Kernel};
-use opencl3::memory::{Buffer, CL_MEM_READ_ONLY, CL_MEM_WRITE_ONLY};
+use opencl3::memory::{Buffer, CL_MEM_WRITE_ONLY};
+use opencl3::platform::get_platforms;
 use opencl3::program::Program;
-use opencl3::types::{cl_event, cl_float, cl_int, CL_BLOCKING, CL_NON_BLOCKING};
+use opencl3::types::{cl_double, cl_event, cl_float, cl_int, CL_BLOCKING, CL_NON_BLOCKING};
 use opencl3::Result;
+use std::mem::size_of;
 use std::ptr;
+use std::time::{Duration, Instant};
+
+use crate::backend::{IterationBuffer, Precision, RenderParams, Renderer, Timings};
+use crate::device::{self, DeviceFilter};
+use crate::kernel::{self, DOUBLE_OPTIONS, KERNEL_NAME, PRELUDE, PROGRAM_SOURCE};
+use crate::viewport::Viewport;
+
+// Opens the first device matching the filter and sets up a context and
+// profiling command queue on it.
+pub(crate) fn open_device(filter: &DeviceFilter) -> std::result::Result<(Device, Context, CommandQueue), String> {
+    let entry = device::select(filter)?;
+    let platforms = get_platforms().map_err(|e| e.to_string())?;
+    let device_id = platforms[entry.platform].get_devices(CL_DEVICE_TYPE_ALL).map_err(|e| e.to_string())?[entry.index];
+    let device = Device::new(device_id);
 
-const PROGRAM_SOURCE: &str = r#"
-        __kernel void mandelbrot(__global float* X, __global float* Y, __global int* RET, int iter) {
-            int id = get_global_id(0);
-            float x0 = X[id];
-            float y0 = Y[id];
-            float x = 0;
-            float y = 0;
-            float x2 = 0;
-            float y2 = 0;
-            int it = 0;
-
-            float q = pow((x0 - (1/4)), 2) + pow(y0, 2);
-            if (q*(q + (x + (1/4))) < (1/4) * pow(y0, 2)) {
-                RET[id] = iter;
-                return;
-            }
+    // Create a Context on an OpenCL device
+    let context = Context::from_device(&device)
+        .map_err(|e| format!("creating a context on {} failed: {}", entry.name, e))?;
 
-            while (pow(x, 2) + pow(y, 2) <= 4 && it < iter) {
-                y = 2*x*y + y0;
-                x = x2 - y2 + x0;
-                x2 = x*x;
-                y2 = y*y;
-                it = it + 1;
-            }
+    // Create a command_queue on the Context's device
+    let queue = CommandQueue::create_default(&context, CL_QUEUE_PROFILING_ENABLE)
+        .map_err(|e| format!("creating a command queue on {} failed: {}", entry.name, e))?;
 
-            RET[id] = it;
-        }
-    "#;
+    Ok((device, context, queue))
+}
 
-const KERNEL_NAME: &str = "mandelbrot";
+// Builds `sources` as one program and creates kernel `name`, build failures
+// carry the log.
+pub(crate) fn build_kernel(context: &Context, sources: &[&str], options: &str, name: &str) -> std::result::Result<Kernel, String> {
+    let program = Program::create_and_build_from_sources(context, sources, options)
+        .map_err(|log| format!("building the {} kernel failed: {}", name, log))?;
+    Kernel::create(&program, name).map_err(|e| e.to_string())
+}
 
-pub(crate) fn main(vec: &mut Vec<i32>, res: u32, max_iter: i32) -> Result<()> {
-    // Find a usable device for this application
-    let device_id = *get_all_devices(CL_DEVICE_TYPE_GPU)?
-        .first()
-        .expect("no device found in platform");
-    let device = Device::new(device_id);
+// device time of a finished command, the queue has to be created with
+// CL_QUEUE_PROFILING_ENABLE
+pub(crate) fn event_duration(event: &Event) -> Result<Duration> {
+    let start = event.profiling_command_start()?;
+    let end = event.profiling_command_end()?;
+    Ok(Duration::from_nanos(end.saturating_sub(start)))
+}
 
-    // Create a Context on an OpenCL device
-    let context = Context::from_device(&device).expect("Context::from_device failed");
+pub(crate) fn supports_fp64(device: &Device) -> Result<bool> {
+    Ok(device.extensions()?.contains("cl_khr_fp64"))
+}
 
-    // Create a command_queue on the Context's device
-    let queue = CommandQueue::create_default(&context, CL_QUEUE_PROFILING_ENABLE)
-        .expect("CommandQueue::create_default failed");
+// Device buffers sized for one frame.
+pub(crate) struct FrameBuffers {
+    len: usize,
+    pub(crate) z: Buffer<cl_int>,
+    pub(crate) smooth: Buffer<cl_float>,
+    pub(crate) period: Buffer<cl_int>,
+}
 
-    // Build the OpenCL program source and create the kernel.
-    let program = Program::create_and_build_from_source(&context, PROGRAM_SOURCE, "")
-        .expect("Program::create_and_build_from_source failed");
-    let kernel = Kernel::create(&program, KERNEL_NAME)?;
+// (Re)allocates `buffers` for frames of `len` pixels unless they already fit.
+pub(crate) fn ensure_buffers(context: &Context, buffers: &mut Option<FrameBuffers>, len: usize) -> Result<()> {
+    if buffers.as_ref().is_none_or(|b| b.len != len) {
+        // Create OpenCL device buffers
+        let z = unsafe {
+            Buffer::<cl_int>::create(context, CL_MEM_WRITE_ONLY, len, ptr::null_mut())?
+        };
+        let smooth = unsafe {
+            Buffer::<cl_float>::create(context, CL_MEM_WRITE_ONLY, len, ptr::null_mut())?
+        };
+        let period = unsafe {
+            Buffer::<cl_int>::create(context, CL_MEM_WRITE_ONLY, len, ptr::null_mut())?
+        };
+        *buffers = Some(FrameBuffers { len, z, smooth, period });
+    }
+    Ok(())
+}
 
-    /////////////////////////////////////////////////////////////////////
-    // Compute data
+// Long-lived OpenCL state, the programs are compiled once and the buffers are
+// only reallocated when the frame size changes.
+pub(crate) struct Ocl3Renderer {
+    device: Device,
+    context: Context,
+    queue: CommandQueue,
+    kernel: Kernel,
+    // double precision kernel, compiled on first use
+    kernel64: Option<Kernel>,
+    buffers: Option<FrameBuffers>,
+    timings: Timings,
+}
 
-    // The input data
-    let mut vec_x:Vec<f32> = Vec::new();
-    let mut vec_y:Vec<f32> = Vec::new();
+impl Ocl3Renderer {
+    pub(crate) fn new(filter: &DeviceFilter) -> std::result::Result<Ocl3Renderer, String> {
+        let (device, context, queue) = open_device(filter)?;
 
-    for i in 0..3 * res {
-        for j in 0..2 * res {
-            vec_x.push((i as f32)/(res as f32) - 2.0);
-            vec_y.push((j as f32)/(res as f32) - 1.0);
+        // Build the OpenCL program source and create the kernel.
+        let kernel = build_kernel(&context, &[PRELUDE, PROGRAM_SOURCE], "", KERNEL_NAME)?;
+
+        Ok(Ocl3Renderer { device, context, queue, kernel, kernel64: None, buffers: None, timings: Timings::default() })
+    }
+
+    fn ensure_kernel64(&mut self) -> std::result::Result<(), String> {
+        if self.kernel64.is_none() {
+            self.kernel64 = Some(build_kernel(&self.context, &[PRELUDE, PROGRAM_SOURCE], DOUBLE_OPTIONS, KERNEL_NAME)?);
         }
+        Ok(())
     }
 
-    let arr_size: usize = vec_x.len();
-
-    // Create OpenCL device buffers
-    let mut x = unsafe {
-        Buffer::<cl_float>::create(&context, CL_MEM_READ_ONLY, arr_size, ptr::null_mut())?
-    };
-    let mut y = unsafe {
-        Buffer::<cl_float>::create(&context, CL_MEM_READ_ONLY, arr_size, ptr::null_mut())?
-    };
-    let z = unsafe {
-        Buffer::<cl_int>::create(&context, CL_MEM_WRITE_ONLY, arr_size, ptr::null_mut())?
-    };
-
-    // Blocking write
-    let _x_write_event = unsafe { queue.enqueue_write_buffer(&mut x, CL_BLOCKING, 0, &vec_x, &[])? };
-
-    // Non-blocking write, wait for y_write_event
-    let y_write_event =
-        unsafe { queue.enqueue_write_buffer(&mut y, CL_NON_BLOCKING, 0, &vec_y, &[])? };
-
-    // Use the ExecuteKernel builder to set the kernel buffer and
-    // cl_float value arguments, before setting the one dimensional
-    // global_work_size for the call to enqueue_nd_range.
-    // Unwraps the Result to get the kernel execution event.
-    let kernel_event = unsafe {
-        ExecuteKernel::new(&kernel)
-            .set_arg(&x)
-            .set_arg(&y)
-            .set_arg(&z)
-            .set_arg(&max_iter)
-            .set_global_work_size(arr_size)
-            .set_wait_event(&y_write_event)
-            .enqueue_nd_range(&queue)?
-    };
-
-    let mut events: Vec<cl_event> = Vec::default();
-    events.push(kernel_event.get());
-
-    // Create a results array to hold the results from the OpenCL device
-    // and enqueue a read command to read the device buffer into the array
-    // after the kernel event completes.
-    let read_event =
-        unsafe { queue.enqueue_read_buffer(&z, CL_NON_BLOCKING, 0, vec, &events)? };
-
-    // Wait for the read_event to complete.
-    read_event.wait()?;
-
-    // Calculate the kernel duration, from the kernel_event
-    let start_time = kernel_event.profiling_command_start()?;
-    let end_time = kernel_event.profiling_command_end()?;
-    let duration = end_time - start_time;
-    println!("kernel execution duration (ns): {}", duration);
+    // the double kernel has to be built already, see ensure_kernel64
+    pub(crate) fn render_into(&mut self, out: &mut IterationBuffer, viewport: &Viewport, params: &RenderParams, double: bool) -> Result<()> {
+        let timer = Instant::now();
+        ensure_buffers(&self.context, &mut self.buffers, viewport.len())?;
+        let queue = &self.queue;
+        let buffers = self.buffers.as_mut().unwrap();
+        let julia = params.julia.is_some() as cl_int;
+        let (cx, cy) = params.julia.unwrap_or_default();
+        let formula = params.formula.kernel_id() as cl_int;
+        let interior_check = params.interior_check as cl_int;
+        let periodicity = params.periodicity as cl_int;
+        let jitter = params.jitter as cl_int;
+
+        // Use the ExecuteKernel builder to set the viewport and buffer
+        // arguments, before setting the two dimensional global work size
+        // for the call to enqueue_nd_range.
+        let mut exec = if double {
+            let mut exec = ExecuteKernel::new(self.kernel64.as_ref().unwrap());
+            unsafe {
+                exec.set_arg(&(viewport.x_min() as cl_double))
+                    .set_arg(&(viewport.y_min() as cl_double))
+                    .set_arg(&(viewport.scale as cl_double))
+                    .set_arg(&buffers.z)
+                    .set_arg(&buffers.smooth)
+                    .set_arg(&params.max_iter)
+                    .set_arg(&(params.bailout() as cl_double))
+                    .set_arg(&julia)
+                    .set_arg(&(cx as cl_double))
+                    .set_arg(&(cy as cl_double))
+                    .set_arg(&formula)
+                    .set_arg(&(params.formula.power() as cl_double))
+                    .set_arg(&interior_check)
+                    .set_arg(&buffers.period)
+                    .set_arg(&periodicity)
+                    .set_arg(&jitter);
+            }
+            exec
+        } else {
+            let mut exec = ExecuteKernel::new(&self.kernel);
+            unsafe {
+                exec.set_arg(&(viewport.x_min() as cl_float))
+                    .set_arg(&(viewport.y_min() as cl_float))
+                    .set_arg(&(viewport.scale as cl_float))
+                    .set_arg(&buffers.z)
+                    .set_arg(&buffers.smooth)
+                    .set_arg(&params.max_iter)
+                    .set_arg(&(params.bailout() as cl_float))
+                    .set_arg(&julia)
+                    .set_arg(&(cx as cl_float))
+                    .set_arg(&(cy as cl_float))
+                    .set_arg(&formula)
+                    .set_arg(&(params.formula.power() as cl_float))
+                    .set_arg(&interior_check)
+                    .set_arg(&buffers.period)
+                    .set_arg(&periodicity)
+                    .set_arg(&jitter);
+            }
+            exec
+        };
+        let setup = timer.elapsed();
+        let kernel_event = unsafe {
+            exec.set_global_work_sizes(&[viewport.width as usize, viewport.height as usize])
+                .enqueue_nd_range(queue)?
+        };
+
+        let mut events: Vec<cl_event> = Vec::default();
+        events.push(kernel_event.get());
+
+        // Enqueue a read command to read the device buffer into the array
+        // after the kernel event completes.
+        let mut reads = vec![
+            unsafe { queue.enqueue_read_buffer(&buffers.z, CL_NON_BLOCKING, 0, &mut out.data, &events)? }
+        ];
+        if let Some(smooth) = out.smooth.as_mut() {
+            reads.push(unsafe { queue.enqueue_read_buffer(&buffers.smooth, CL_BLOCKING, 0, smooth, &events)? });
+        }
+        if let Some(period) = out.period.as_mut() {
+            reads.push(unsafe { queue.enqueue_read_buffer(&buffers.period, CL_BLOCKING, 0, period, &events)? });
+        }
 
-    Ok(())
+        // Wait for the first read to complete.
+        reads[0].wait()?;
+
+        let mut readback = Duration::ZERO;
+        for read in &reads {
+            readback += event_duration(read)?;
+        }
+        self.timings = Timings { setup, upload: Duration::ZERO, kernel: event_duration(&kernel_event)?, readback };
+        Ok(())
+    }
+}
+
+impl Renderer for Ocl3Renderer {
+    fn name(&self) -> &'static str {
+        "opencl3"
+    }
+
+    // every frame buffer holds 4 byte elements
+    fn max_pixels(&self) -> Option<usize> {
+        self.device.max_mem_alloc_size().ok().map(|bytes| bytes as usize / size_of::<cl_int>())
+    }
+
+    fn timings(&self) -> Option<Timings> {
+        Some(self.timings)
+    }
+
+    fn render(&mut self, viewport: &Viewport, params: &RenderParams) -> std::result::Result<IterationBuffer, String> {
+        let double = params.precision.resolve(viewport) == Precision::Double;
+        if double && !supports_fp64(&self.device).map_err(|e| e.to_string())? {
+            let name = self.device.name().map_err(|e| e.to_string())?;
+            return Err(kernel::fp64_unavailable(&name));
+        }
+        if double {
+            self.ensure_kernel64()?;
+        }
+
+        let mut buffer = IterationBuffer::for_params(viewport.width, viewport.height, params);
+        self.render_into(&mut buffer, viewport, params, double).map_err(|e| e.to_string())?;
+        Ok(buffer)
+    }
 }
@@ -0,0 +1,307 @@
+// Colouring of iteration buffers.
+//
+// A palette is a gradient of colour stops over [0, 1]. Escape counts are
+// first mapped to [0, 1] (linearly, logarithmically or by histogram
+// equalization), then scaled by `density`, shifted by `offset` and either
+// wrapped (cyclic palettes) or clamped before the gradient lookup. Pixels
+// that reached max_iter get the interior colour, or a gradient colour picked
+// by their cycle length when the buffer carries periods.
+//
+// Palette files are plain text, one setting per line, `#` starts a comment:
+//
+//     name fire
+//     cyclic true
+//     density 4
+//     offset 0.25
+//     mapping log
+//     interior 0 0 0
+//     stop 0.0 0 0 0
+//     stop 0.5 255 128 0
+//     stop 1.0 255 255 255
+
+use std::fmt::Display;
+use std::fs;
+use std::str::FromStr;
+
+use crate::backend::IterationBuffer;
+
+pub(crate) const PALETTE_NAMES: [&str; 4] = ["grey", "fire", "ocean", "rainbow"];
+
+// gradient step between consecutive periods, the golden ratio conjugate
+// keeps components of nearby periods apart in colour
+const PERIOD_SPACING: f32 = 0.618034;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Rgb(pub(crate) u8, pub(crate) u8, pub(crate) u8);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Mapping {
+    Linear,
+    Log,
+    Histogram,
+}
+
+impl Mapping {
+    pub(crate) fn from_name(name: &str) -> Result<Mapping, String> {
+        match name {
+            "linear" => Ok(Mapping::Linear),
+            "log" => Ok(Mapping::Log),
+            "histogram" => Ok(Mapping::Histogram),
+            _ => Err(format!("unknown mapping '{}', expected linear, log or histogram", name)),
+        }
+    }
+
+    pub(crate) fn next(self) -> Mapping {
+        match self {
+            Mapping::Linear => Mapping::Log,
+            Mapping::Log => Mapping::Histogram,
+            Mapping::Histogram => Mapping::Linear,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Palette {
+    pub(crate) name: String,
+    // builtin name or file path the palette was loaded from, what bookmarks
+    // record
+    pub(crate) source: String,
+    // (position in [0, 1], colour), sorted by position
+    pub(crate) stops: Vec<(f32, Rgb)>,
+    pub(crate) cyclic: bool,
+    pub(crate) offset: f32,
+    pub(crate) density: f32,
+    pub(crate) mapping: Mapping,
+    pub(crate) interior: Rgb,
+}
+
+impl Palette {
+    fn new(name: &str, stops: &[(f32, Rgb)], cyclic: bool, density: f32) -> Palette {
+        Palette {
+            name: name.to_string(),
+            source: name.to_string(),
+            stops: stops.to_vec(),
+            cyclic,
+            offset: 0.0,
+            density,
+            mapping: Mapping::Linear,
+            interior: Rgb(0, 0, 0),
+        }
+    }
+
+    pub(crate) fn builtin(name: &str) -> Option<Palette> {
+        match name {
+            "grey" => Some(Palette::new(name, &[(0.0, Rgb(0, 0, 0)), (1.0, Rgb(255, 255, 255))], false, 1.0)),
+            "fire" => Some(Palette::new(name, &[
+                (0.0, Rgb(0, 0, 0)),
+                (0.3, Rgb(160, 20, 0)),
+                (0.6, Rgb(255, 150, 0)),
+                (1.0, Rgb(255, 255, 220)),
+            ], false, 1.0)),
+            "ocean" => Some(Palette::new(name, &[
+                (0.0, Rgb(0, 7, 100)),
+                (0.16, Rgb(32, 107, 203)),
+                (0.42, Rgb(237, 255, 255)),
+                (0.64, Rgb(255, 170, 0)),
+                (0.86, Rgb(0, 2, 0)),
+                (1.0, Rgb(0, 7, 100)),
+            ], true, 8.0)),
+            "rainbow" => Some(Palette::new(name, &[
+                (0.0, Rgb(255, 0, 0)),
+                (0.17, Rgb(255, 255, 0)),
+                (0.33, Rgb(0, 255, 0)),
+                (0.5, Rgb(0, 255, 255)),
+                (0.67, Rgb(0, 0, 255)),
+                (0.83, Rgb(255, 0, 255)),
+                (1.0, Rgb(255, 0, 0)),
+            ], true, 4.0)),
+            _ => None,
+        }
+    }
+
+    // a builtin palette name or the path of a palette file
+    pub(crate) fn by_name_or_file(name: &str) -> Result<Palette, String> {
+        match Palette::builtin(name) {
+            Some(palette) => Ok(palette),
+            None => Palette::load(name),
+        }
+    }
+
+    pub(crate) fn load(path: &str) -> Result<Palette, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut palette = Palette::parse(&text).map_err(|e| format!("{}: {}", path, e))?;
+        palette.source = path.to_string();
+        Ok(palette)
+    }
+
+    pub(crate) fn parse(text: &str) -> Result<Palette, String> {
+        let mut palette = Palette::new("custom", &[], false, 1.0);
+        for line in setting_lines(text) {
+            let colour = |i: usize| -> Result<Rgb, String> {
+                if line.len() < i + 3 {
+                    return Err(line.err("expected three colour channels"));
+                }
+                Ok(Rgb(line.value(i)?, line.value(i + 1)?, line.value(i + 2)?))
+            };
+            match line.name() {
+                "name" => palette.name = line.rest(1),
+                "cyclic" => palette.cyclic = line.word(1) == Ok("true"),
+                "offset" => palette.offset = line.value(1)?,
+                "density" => palette.density = line.value(1)?,
+                "mapping" => palette.mapping = Mapping::from_name(line.word(1).unwrap_or("")).map_err(|e| line.err(&e))?,
+                "interior" => palette.interior = colour(1)?,
+                "stop" => palette.stops.push((line.value(1)?, colour(2)?)),
+                other => return Err(line.err(&format!("unknown setting '{}'", other))),
+            }
+        }
+        if palette.stops.len() < 2 {
+            return Err("a palette needs at least two stops".to_string());
+        }
+        palette.stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Ok(palette)
+    }
+
+    // gradient lookup, `t` is wrapped or clamped into [0, 1] first
+    pub(crate) fn sample(&self, t: f32) -> Rgb {
+        let t = if self.cyclic { t.rem_euclid(1.0) } else { t.clamp(0.0, 1.0) };
+        let upper = self.stops.iter().position(|s| s.0 >= t).unwrap_or(self.stops.len() - 1);
+        if upper == 0 {
+            return self.stops[0].1;
+        }
+        let (p0, c0) = self.stops[upper - 1];
+        let (p1, c1) = self.stops[upper];
+        let f = if p1 > p0 { (t - p0) / (p1 - p0) } else { 0.0 };
+        let lerp = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * f).round() as u8;
+        Rgb(lerp(c0.0, c1.0), lerp(c0.1, c1.1), lerp(c0.2, c1.2))
+    }
+
+    // colours a whole frame, row by row like the buffer
+    pub(crate) fn colorize(&self, buffer: &IterationBuffer, max_iter: i32) -> Vec<Rgb> {
+        let histogram = match self.mapping {
+            Mapping::Histogram => Some(cumulative_histogram(buffer, max_iter)),
+            _ => None,
+        };
+        self.colorize_with(buffer, max_iter, histogram.as_deref())
+    }
+
+    // like colorize, but histogram mapping uses `histogram` (see
+    // cumulative_histogram) instead of the buffer's own, so the parts of a
+    // tiled image share one mapping
+    pub(crate) fn colorize_with(&self, buffer: &IterationBuffer, max_iter: i32, histogram: Option<&[f32]>) -> Vec<Rgb> {
+        let histogram = histogram.filter(|_| self.mapping == Mapping::Histogram);
+
+        let mut pixels = Vec::with_capacity(buffer.data.len());
+        for j in 0..buffer.height {
+            for i in 0..buffer.width {
+                pixels.push(self.colour(buffer, i, j, max_iter, histogram));
+            }
+        }
+        pixels
+    }
+
+    // colorize written straight into RGBA rows `pitch` bytes apart, the
+    // layout of a locked streaming texture
+    pub(crate) fn colorize_rgba(&self, buffer: &IterationBuffer, max_iter: i32, out: &mut [u8], pitch: usize) {
+        let histogram = match self.mapping {
+            Mapping::Histogram => Some(cumulative_histogram(buffer, max_iter)),
+            _ => None,
+        };
+        for j in 0..buffer.height {
+            let row = &mut out[j as usize * pitch..];
+            for i in 0..buffer.width {
+                let c = self.colour(buffer, i, j, max_iter, histogram.as_deref());
+                let at = i as usize * 4;
+                row[at..at + 4].copy_from_slice(&[c.0, c.1, c.2, 255]);
+            }
+        }
+    }
+
+    fn colour(&self, buffer: &IterationBuffer, i: u32, j: u32, max_iter: i32, histogram: Option<&[f32]>) -> Rgb {
+        if buffer.get(i, j) >= max_iter {
+            let period = buffer.period.as_ref().map_or(0, |periods| periods[j as usize * buffer.width as usize + i as usize]);
+            if period > 0 {
+                return self.sample((period as f32 * PERIOD_SPACING).fract());
+            }
+            return self.interior;
+        }
+        let v = buffer.value(i, j).max(0.0);
+        let t = match histogram {
+            Some(cdf) => {
+                let k = (v as usize).min(cdf.len() - 2);
+                let f = v - k as f32;
+                cdf[k] + (cdf[k + 1] - cdf[k]) * f
+            }
+            None if self.mapping == Mapping::Log => (1.0 + v).ln() / (1.0 + max_iter as f32).ln(),
+            None => v / max_iter as f32,
+        };
+        self.sample(t * self.density + self.offset)
+    }
+}
+
+// One setting of a palette, bookmark or script file: a name followed by
+// its values, errors carry the line number.
+pub(crate) struct SettingLine<'a> {
+    // 0-based
+    n: usize,
+    words: Vec<&'a str>,
+}
+
+impl<'a> SettingLine<'a> {
+    pub(crate) fn name(&self) -> &'a str {
+        self.words[0]
+    }
+
+    // number of words including the name
+    pub(crate) fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub(crate) fn err(&self, msg: &str) -> String {
+        format!("line {}: {}", self.n + 1, msg)
+    }
+
+    pub(crate) fn word(&self, i: usize) -> Result<&'a str, String> {
+        self.words.get(i).copied().ok_or_else(|| self.err("missing value"))
+    }
+
+    pub(crate) fn value<T: FromStr>(&self, i: usize) -> Result<T, String>
+    where
+        T::Err: Display,
+    {
+        self.word(i)?.parse::<T>().map_err(|e| self.err(&e.to_string()))
+    }
+
+    // the words from `i` on joined by single spaces, empty when there are
+    // none
+    pub(crate) fn rest(&self, i: usize) -> String {
+        self.words.get(i..).unwrap_or_default().join(" ")
+    }
+}
+
+// the non-empty lines of a settings file with comments removed
+pub(crate) fn setting_lines(text: &str) -> impl Iterator<Item = SettingLine<'_>> {
+    text.lines().enumerate().filter_map(|(n, line)| {
+        let words: Vec<&str> = line.split('#').next().unwrap().split_whitespace().collect();
+        (!words.is_empty()).then_some(SettingLine { n, words })
+    })
+}
+
+// fraction of exterior pixels that escaped in fewer than k iterations,
+// for k in 0..=max_iter
+pub(crate) fn cumulative_histogram(buffer: &IterationBuffer, max_iter: i32) -> Vec<f32> {
+    let mut counts = vec![0u64; max_iter.max(1) as usize + 1];
+    let mut total = 0u64;
+    for &it in &buffer.data {
+        if it < max_iter {
+            counts[it.max(0) as usize] += 1;
+            total += 1;
+        }
+    }
+    let mut cdf = Vec::with_capacity(counts.len());
+    let mut sum = 0u64;
+    for count in counts {
+        cdf.push(if total > 0 { sum as f32 / total as f32 } else { 0.0 });
+        sum += count;
+    }
+    cdf
+}
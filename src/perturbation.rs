@@ -0,0 +1,256 @@
+use opencl3::command_queue::CommandQueue;
+use opencl3::context::Context;
+use opencl3::device::Device;
+use opencl3::kernel::{ExecuteKernel, Kernel};
+use opencl3::memory::{Buffer, CL_MEM_READ_ONLY};
+use opencl3::types::{cl_double, cl_float, cl_int, CL_BLOCKING};
+use opencl3::Result;
+use std::mem::size_of;
+use std::ptr;
+use std::time::{Duration, Instant};
+
+use crate::backend::{Formula, IterationBuffer, Precision, RenderParams, Renderer, Timings};
+use crate::dd::Dd;
+use crate::device::DeviceFilter;
+use crate::kernel::{self, DOUBLE_OPTIONS, PERTURBATION_KERNEL_NAME, PERTURBATION_SOURCE, PRELUDE};
+use crate::ocl3;
+use crate::viewport::Viewport;
+
+// Float deltas run into the bottom of the float range (about 1e-38) past
+// this pixel spacing. Unless the center is within about 1e-5 of the origin
+// the depth limit of the Dd center, see Viewport::min_scale, comes first.
+const SINGLE_MIN_SCALE: f64 = 1e-35;
+
+// pixel spacing in ulps of `real` below which the kernel's absolute
+// coordinates no longer tell neighbouring pixels apart
+const RESOLVE_MARGIN: f64 = 4.0;
+
+// whether steps of `spacing` on values around `magnitude` survive rounding
+// to the kernel's `real`
+fn resolves(spacing: f64, magnitude: f64, double: bool) -> bool {
+    let epsilon = if double { f64::EPSILON } else { f32::EPSILON as f64 };
+    spacing >= magnitude * epsilon * RESOLVE_MARGIN
+}
+
+// Reference orbit Z_n+1 = Z_n^2 + C from Z_0 = (zx, zy) computed in
+// double-double, stopping once it escapes.
+pub(crate) fn reference_orbit(mut zx: Dd, mut zy: Dd, cx: Dd, cy: Dd, max_iter: i32) -> (Vec<f64>, Vec<f64>) {
+    let mut orbit_x = vec![zx.to_f64()];
+    let mut orbit_y = vec![zy.to_f64()];
+    for _ in 0..max_iter {
+        let x2 = zx.sqr();
+        let y2 = zy.sqr();
+        zy = zx * zy * 2.0 + cy;
+        zx = x2 - y2 + cx;
+        orbit_x.push(zx.to_f64());
+        orbit_y.push(zy.to_f64());
+        if (zx.sqr() + zy.sqr()).to_f64() > 4.0 {
+            break;
+        }
+    }
+    (orbit_x, orbit_y)
+}
+
+struct OrbitBuffers<T> {
+    len: usize,
+    x: Buffer<T>,
+    y: Buffer<T>,
+}
+
+// Deep zoom renderer: reference orbit on the host, delta iteration on the
+// device.
+pub(crate) struct PerturbationRenderer {
+    device: Device,
+    context: Context,
+    queue: CommandQueue,
+    kernel: Kernel,
+    // double precision kernel, compiled on first use
+    kernel64: Option<Kernel>,
+    orbit: Option<OrbitBuffers<cl_float>>,
+    orbit64: Option<OrbitBuffers<cl_double>>,
+    frame: Option<ocl3::FrameBuffers>,
+    timings: Timings,
+}
+
+impl PerturbationRenderer {
+    pub(crate) fn new(filter: &DeviceFilter) -> std::result::Result<PerturbationRenderer, String> {
+        let (device, context, queue) = ocl3::open_device(filter)?;
+
+        let kernel = ocl3::build_kernel(&context, &[PRELUDE, PERTURBATION_SOURCE], "", PERTURBATION_KERNEL_NAME)?;
+
+        Ok(PerturbationRenderer { device, context, queue, kernel, kernel64: None, orbit: None, orbit64: None, frame: None,
+            timings: Timings::default() })
+    }
+
+    fn ensure_kernel64(&mut self) -> std::result::Result<(), String> {
+        if self.kernel64.is_none() {
+            self.kernel64 = Some(ocl3::build_kernel(&self.context, &[PRELUDE, PERTURBATION_SOURCE], DOUBLE_OPTIONS, PERTURBATION_KERNEL_NAME)?);
+        }
+        Ok(())
+    }
+
+    // returns the device time of the copies
+    fn upload_orbit<T: Copy>(context: &Context, queue: &CommandQueue, slot: &mut Option<OrbitBuffers<T>>, x: &[T], y: &[T]) -> Result<Duration> {
+        if slot.as_ref().is_none_or(|b| b.len < x.len()) {
+            let bx = unsafe { Buffer::<T>::create(context, CL_MEM_READ_ONLY, x.len(), ptr::null_mut())? };
+            let by = unsafe { Buffer::<T>::create(context, CL_MEM_READ_ONLY, y.len(), ptr::null_mut())? };
+            *slot = Some(OrbitBuffers { len: x.len(), x: bx, y: by });
+        }
+        let buffers = slot.as_mut().unwrap();
+        let (write_x, write_y) = unsafe {
+            (queue.enqueue_write_buffer(&mut buffers.x, CL_BLOCKING, 0, x, &[])?,
+             queue.enqueue_write_buffer(&mut buffers.y, CL_BLOCKING, 0, y, &[])?)
+        };
+        Ok(ocl3::event_duration(&write_x)? + ocl3::event_duration(&write_y)?)
+    }
+
+    // the double kernel has to be built already, see ensure_kernel64
+    pub(crate) fn render_into(&mut self, out: &mut IterationBuffer, viewport: &Viewport, params: &RenderParams, double: bool) -> Result<()> {
+        let timer = Instant::now();
+        // the view center is C for the Mandelbrot set and Z_0 for Julia sets
+        let (orbit_x, orbit_y) = match params.julia {
+            Some((cx, cy)) => reference_orbit(viewport.center_x, viewport.center_y, Dd::from(cx), Dd::from(cy), params.max_iter),
+            None => reference_orbit(Dd::default(), Dd::default(), viewport.center_x, viewport.center_y, params.max_iter),
+        };
+        let julia = params.julia.is_some() as cl_int;
+        // the bulb test rebuilds C = ref_c + dc in `real`, which collapses
+        // the whole view onto the center past the resolution limit
+        let magnitude = viewport.center_x.to_f64().abs().max(viewport.center_y.to_f64().abs());
+        let interior_check = (params.interior_check && resolves(viewport.scale, magnitude, double)) as cl_int;
+        // cycles are caught on the full orbit Z + d within step / 1024, below
+        // the rounding error of Z in `real` that only holds for values that
+        // round to the same number, and orbits shadowing the reference's
+        // cycle near a minibrot would all look periodic
+        let orbit_max = orbit_x.iter().chain(&orbit_y).fold(0.0, |max: f64, v| max.max(v.abs()));
+        let periodicity = (params.periodicity && resolves(viewport.scale / 1024.0, orbit_max, double)) as cl_int;
+        let jitter = params.jitter as cl_int;
+        let (ref_cx, ref_cy) = (viewport.center_x.to_f64(), viewport.center_y.to_f64());
+        let ref_len = orbit_x.len() as cl_int;
+
+        // pixel offsets relative to the reference at the center
+        let dx_min = -(viewport.width as f64 / 2.0) * viewport.scale;
+        let dy_min = -(viewport.height as f64 / 2.0) * viewport.scale;
+
+        ocl3::ensure_buffers(&self.context, &mut self.frame, viewport.len())?;
+        let upload_timer = Instant::now();
+        let upload = if double {
+            Self::upload_orbit(&self.context, &self.queue, &mut self.orbit64, &orbit_x, &orbit_y)?
+        } else {
+            let orbit_x: Vec<cl_float> = orbit_x.iter().map(|&v| v as cl_float).collect();
+            let orbit_y: Vec<cl_float> = orbit_y.iter().map(|&v| v as cl_float).collect();
+            Self::upload_orbit(&self.context, &self.queue, &mut self.orbit, &orbit_x, &orbit_y)?
+        };
+        // the copies are blocking, their wall time is not setup
+        let upload_wall = upload_timer.elapsed();
+
+        let frame = self.frame.as_ref().unwrap();
+        let mut exec = if double {
+            let orbit = self.orbit64.as_ref().unwrap();
+            let mut exec = ExecuteKernel::new(self.kernel64.as_ref().unwrap());
+            unsafe {
+                exec.set_arg(&orbit.x)
+                    .set_arg(&orbit.y)
+                    .set_arg(&ref_len)
+                    .set_arg(&(dx_min as cl_double))
+                    .set_arg(&(dy_min as cl_double))
+                    .set_arg(&(viewport.scale as cl_double))
+                    .set_arg(&frame.z)
+                    .set_arg(&frame.smooth)
+                    .set_arg(&params.max_iter)
+                    .set_arg(&(params.bailout() as cl_double))
+                    .set_arg(&julia)
+                    .set_arg(&interior_check)
+                    .set_arg(&(ref_cx as cl_double))
+                    .set_arg(&(ref_cy as cl_double))
+                    .set_arg(&frame.period)
+                    .set_arg(&periodicity)
+                    .set_arg(&jitter);
+            }
+            exec
+        } else {
+            let orbit = self.orbit.as_ref().unwrap();
+            let mut exec = ExecuteKernel::new(&self.kernel);
+            unsafe {
+                exec.set_arg(&orbit.x)
+                    .set_arg(&orbit.y)
+                    .set_arg(&ref_len)
+                    .set_arg(&(dx_min as cl_float))
+                    .set_arg(&(dy_min as cl_float))
+                    .set_arg(&(viewport.scale as cl_float))
+                    .set_arg(&frame.z)
+                    .set_arg(&frame.smooth)
+                    .set_arg(&params.max_iter)
+                    .set_arg(&(params.bailout() as cl_float))
+                    .set_arg(&julia)
+                    .set_arg(&interior_check)
+                    .set_arg(&(ref_cx as cl_float))
+                    .set_arg(&(ref_cy as cl_float))
+                    .set_arg(&frame.period)
+                    .set_arg(&periodicity)
+                    .set_arg(&jitter);
+            }
+            exec
+        };
+
+        let setup = timer.elapsed().saturating_sub(upload_wall);
+        let kernel_event = unsafe {
+            exec.set_global_work_sizes(&[viewport.width as usize, viewport.height as usize])
+                .enqueue_nd_range(&self.queue)?
+        };
+
+        let mut reads = Vec::new();
+        unsafe {
+            reads.push(self.queue.enqueue_read_buffer(&frame.z, CL_BLOCKING, 0, &mut out.data, &[kernel_event.get()])?);
+            if let Some(smooth) = out.smooth.as_mut() {
+                reads.push(self.queue.enqueue_read_buffer(&frame.smooth, CL_BLOCKING, 0, smooth, &[kernel_event.get()])?);
+            }
+            if let Some(period) = out.period.as_mut() {
+                reads.push(self.queue.enqueue_read_buffer(&frame.period, CL_BLOCKING, 0, period, &[kernel_event.get()])?);
+            }
+        }
+
+        let mut readback = Duration::ZERO;
+        for read in &reads {
+            readback += ocl3::event_duration(read)?;
+        }
+        self.timings = Timings { setup, upload, kernel: ocl3::event_duration(&kernel_event)?, readback };
+        Ok(())
+    }
+}
+
+impl Renderer for PerturbationRenderer {
+    fn name(&self) -> &'static str {
+        "perturbation"
+    }
+
+    fn max_pixels(&self) -> Option<usize> {
+        self.device.max_mem_alloc_size().ok().map(|bytes| bytes as usize / size_of::<cl_int>())
+    }
+
+    fn timings(&self) -> Option<Timings> {
+        Some(self.timings)
+    }
+
+    fn render(&mut self, viewport: &Viewport, params: &RenderParams) -> std::result::Result<IterationBuffer, String> {
+        // the delta recurrence is derived for z^2 + c only
+        if params.formula != Formula::Mandelbrot {
+            return Err(format!("the perturbation backend only supports the mandelbrot formula, not {:?}", params.formula));
+        }
+        viewport.check_depth()?;
+        let double = match params.precision {
+            Precision::Auto => viewport.scale < SINGLE_MIN_SCALE,
+            p => p == Precision::Double,
+        };
+        if double && !ocl3::supports_fp64(&self.device).map_err(|e| e.to_string())? {
+            let name = self.device.name().map_err(|e| e.to_string())?;
+            return Err(kernel::fp64_unavailable(&name));
+        }
+        if double {
+            self.ensure_kernel64()?;
+        }
+
+        let mut buffer = IterationBuffer::for_params(viewport.width, viewport.height, params);
+        self.render_into(&mut buffer, viewport, params, double).map_err(|e| e.to_string())?;
+        Ok(buffer)
+    }
+}
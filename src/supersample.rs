@@ -0,0 +1,144 @@
+// Supersampling anti-aliasing.
+//
+// A view is rendered at `samples` x `samples` times the pixel count, each
+// sample is coloured and every block of samples is averaged into one pixel.
+// The average is taken in linear light, averaging the sRGB bytes directly
+// would darken edges between bright and dark areas.
+//
+// The grid pattern puts the samples at the centers of a regular subgrid of
+// the pixel. The jittered pattern moves every sample to a pseudo-random
+// point inside its cell of that subgrid (stratified sampling), which turns
+// the moire of fine structure into noise.
+
+use crate::backend::{IterationBuffer, RenderParams, Renderer};
+use crate::palette::{Palette, Rgb};
+use crate::viewport::Viewport;
+
+// largest accepted samples per pixel along each axis
+pub(crate) const MAX_SAMPLES: u32 = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Pattern {
+    Grid,
+    Jittered,
+}
+
+impl Pattern {
+    pub(crate) fn from_name(name: &str) -> Result<Pattern, String> {
+        match name {
+            "grid" => Ok(Pattern::Grid),
+            "jitter" | "jittered" => Ok(Pattern::Jittered),
+            _ => Err(format!("unknown sampling pattern '{}', expected grid or jitter", name)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Sampling {
+    // samples per pixel along each axis, 1 turns supersampling off
+    pub(crate) samples: u32,
+    pub(crate) pattern: Pattern,
+}
+
+impl Sampling {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.samples > 1
+    }
+
+    // the view holding every sample, sample (a, b) of pixel (i, j) is its
+    // pixel (i * samples + a, j * samples + b), fails when that view is too
+    // large to address
+    pub(crate) fn viewport(&self, viewport: &Viewport) -> Result<Viewport, String> {
+        let n = self.samples;
+        let scaled = |side: u32| (side as usize).checked_mul(n as usize).and_then(|side| u32::try_from(side).ok())
+            .ok_or_else(|| format!("{0}x{0} samples per pixel of a {1}x{2} view are too many", n, viewport.width, viewport.height));
+        let mut fine = Viewport { width: scaled(viewport.width)?, height: scaled(viewport.height)?, scale: viewport.scale / n as f64, ..*viewport };
+        // center the subgrid on the sample position of the pixel
+        let shift = (1.0 / n as f64 - 1.0) / 2.0 * viewport.scale;
+        fine.center_x = fine.center_x + shift;
+        fine.center_y = fine.center_y + shift;
+        Ok(fine)
+    }
+
+    pub(crate) fn params(&self, params: &RenderParams) -> RenderParams {
+        RenderParams { jitter: self.pattern == Pattern::Jittered, ..*params }
+    }
+
+    // renders and colours `viewport`, one colour per pixel
+    pub(crate) fn render(&self, renderer: &mut dyn Renderer, viewport: &Viewport, params: &RenderParams,
+                         palette: &Palette, histogram: Option<&[f32]>) -> Result<Vec<Rgb>, String> {
+        let buffer = renderer.render(&self.viewport(viewport)?, &self.params(params))?;
+        Ok(self.resolve(&buffer, params.max_iter, palette, histogram))
+    }
+
+    // colours a buffer rendered with `viewport()` and averages it down
+    pub(crate) fn resolve(&self, buffer: &IterationBuffer, max_iter: i32, palette: &Palette, histogram: Option<&[f32]>) -> Vec<Rgb> {
+        let colours = match histogram {
+            Some(cdf) => palette.colorize_with(buffer, max_iter, Some(cdf)),
+            None => palette.colorize(buffer, max_iter),
+        };
+        downsample(&colours, buffer.width, buffer.height, self.samples)
+    }
+}
+
+impl Default for Sampling {
+    fn default() -> Sampling {
+        Sampling { samples: 1, pattern: Pattern::Grid }
+    }
+}
+
+// Offset in [-0.5, 0.5)^2 of the jittered sample for pixel (i, j), the same
+// hash as jitter_offset in the kernels.
+pub(crate) fn jitter(i: u32, j: u32) -> (f64, f64) {
+    fn mix_bits(mut x: u32) -> u32 {
+        x ^= x >> 16;
+        x = x.wrapping_mul(0x7feb352d);
+        x ^= x >> 15;
+        x = x.wrapping_mul(0x846ca68b);
+        x ^= x >> 16;
+        x
+    }
+    let h = mix_bits(i.wrapping_add(mix_bits(j)));
+    ((h & 0xffff) as f64 / 65536.0 - 0.5, (h >> 16) as f64 / 65536.0 - 0.5)
+}
+
+fn to_linear(c: u8) -> f32 {
+    let c = c as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn to_srgb(c: f32) -> u8 {
+    let c = if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 };
+    (c * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+// averages every `n` x `n` block of a `width` x `height` image in linear light
+pub(crate) fn downsample(pixels: &[Rgb], width: u32, height: u32, n: u32) -> Vec<Rgb> {
+    if n == 1 {
+        return pixels.to_vec();
+    }
+    let linear: Vec<f32> = (0..=255u8).map(to_linear).collect();
+    let (out_width, out_height) = (width / n, height / n);
+    let count = (n * n) as f32;
+    let mut out = Vec::with_capacity(out_width as usize * out_height as usize);
+    for j in 0..out_height {
+        for i in 0..out_width {
+            let mut sum = [0.0f32; 3];
+            for b in 0..n {
+                let row = (j * n + b) as usize * width as usize;
+                for a in 0..n {
+                    let c = pixels[row + (i * n + a) as usize];
+                    sum[0] += linear[c.0 as usize];
+                    sum[1] += linear[c.1 as usize];
+                    sum[2] += linear[c.2 as usize];
+                }
+            }
+            out.push(Rgb(to_srgb(sum[0] / count), to_srgb(sum[1] / count), to_srgb(sum[2] / count)));
+        }
+    }
+    out
+}
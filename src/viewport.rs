@@ -0,0 +1,123 @@
+// A rectangle of the complex plane mapped onto a pixel grid.
+//
+// Pixel (0, 0) is the top left corner, pixel buffers are stored row by row,
+// so pixel (i, j) lives at index j * width + i.
+//
+// The center is a double-double so the view can be moved around at zooms
+// where a single f64 no longer tells neighbouring pixels apart.
+
+use crate::dd::Dd;
+
+// pixel spacing in Dd ulps of the center below which views are refused
+const DEPTH_MARGIN: f64 = 16.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Viewport {
+    pub(crate) center_x: Dd,
+    pub(crate) center_y: Dd,
+    // distance in the complex plane between two neighbouring pixels
+    pub(crate) scale: f64,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+}
+
+impl Viewport {
+    pub(crate) fn new(center_x: f64, center_y: f64, scale: f64, width: u32, height: u32) -> Viewport {
+        Viewport { center_x: Dd::from(center_x), center_y: Dd::from(center_y), scale, width, height }
+    }
+
+    // the [-2,1]x[-1,1] overview fitted into the given pixel size
+    pub(crate) fn overview(width: u32, height: u32) -> Viewport {
+        let scale = f64::max(3.0 / width as f64, 2.0 / height as f64);
+        Viewport::new(-0.5, 0.0, scale, width, height)
+    }
+
+    // the [-2,2]x[-1.5,1.5] square around the origin that holds every
+    // connected Julia set
+    pub(crate) fn julia_overview(width: u32, height: u32) -> Viewport {
+        let scale = f64::max(4.0 / width as f64, 3.0 / height as f64);
+        Viewport::new(0.0, 0.0, scale, width, height)
+    }
+
+    // julia_overview for a Julia set, overview otherwise
+    pub(crate) fn overview_of(julia: bool, width: u32, height: u32) -> Viewport {
+        if julia {
+            Viewport::julia_overview(width, height)
+        } else {
+            Viewport::overview(width, height)
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
+    pub(crate) fn x_min(&self) -> f64 {
+        (self.center_x + -(self.width as f64 / 2.0 * self.scale)).to_f64()
+    }
+
+    pub(crate) fn y_min(&self) -> f64 {
+        (self.center_y + -(self.height as f64 / 2.0 * self.scale)).to_f64()
+    }
+
+    pub(crate) fn index(&self, i: u32, j: u32) -> usize {
+        j as usize * self.width as usize + i as usize
+    }
+
+    pub(crate) fn pixel_to_complex(&self, i: f64, j: f64) -> (f64, f64) {
+        (self.x_min() + i * self.scale, self.y_min() + j * self.scale)
+    }
+
+    // Finest pixel spacing the double-double center resolves, a few ulps of
+    // the larger center coordinate. Past it neighbouring views round to the
+    // same center and the reference orbit is off by whole pixels.
+    pub(crate) fn min_scale(&self) -> f64 {
+        self.center_x.hi.abs().max(self.center_y.hi.abs()) * Dd::EPSILON * DEPTH_MARGIN
+    }
+
+    pub(crate) fn check_depth(&self) -> Result<(), String> {
+        if self.scale < self.min_scale() {
+            return Err(format!("pixel spacing {:e} is past the depth limit of {:e} at this center", self.scale, self.min_scale()));
+        }
+        Ok(())
+    }
+
+    // moves the view by a number of pixels
+    pub(crate) fn pan(&mut self, di: f64, dj: f64) {
+        self.center_x = self.center_x + di * self.scale;
+        self.center_y = self.center_y + dj * self.scale;
+    }
+
+    // multiplies the pixel spacing by `factor`, keeping the point under
+    // pixel (i, j) in place
+    pub(crate) fn zoom_at(&mut self, i: f64, j: f64, factor: f64) {
+        let di = i - self.width as f64 / 2.0;
+        let dj = j - self.height as f64 / 2.0;
+        self.pan(di * (1.0 - factor), dj * (1.0 - factor));
+        self.scale *= factor;
+    }
+
+    pub(crate) fn zoom(&mut self, factor: f64) {
+        self.scale *= factor;
+    }
+
+    // the `width` x `height` pixel part of this view whose top left corner
+    // is pixel (i, j)
+    pub(crate) fn tile(&self, i: u32, j: u32, width: u32, height: u32) -> Viewport {
+        let dx = (i as f64 + width as f64 / 2.0 - self.width as f64 / 2.0) * self.scale;
+        let dy = (j as f64 + height as f64 / 2.0 - self.height as f64 / 2.0) * self.scale;
+        Viewport { center_x: self.center_x + dx, center_y: self.center_y + dy, scale: self.scale, width, height }
+    }
+
+    // the same area sampled every `factor` pixels: pixel (i, j) of the
+    // result lies exactly on pixel (factor * i, factor * j) of this view
+    pub(crate) fn coarse(&self, factor: u32) -> Viewport {
+        let width = self.width.div_ceil(factor);
+        let height = self.height.div_ceil(factor);
+        let scale = self.scale * factor as f64;
+        // move the center so x_min and y_min stay put
+        let dx = width as f64 / 2.0 * scale - self.width as f64 / 2.0 * self.scale;
+        let dy = height as f64 / 2.0 * scale - self.height as f64 / 2.0 * self.scale;
+        Viewport { center_x: self.center_x + dx, center_y: self.center_y + dy, scale, width, height }
+    }
+}
@@ -0,0 +1,149 @@
+// Background rendering for the viewer.
+//
+// The worker thread owns the backend and renders the progressive passes of
+// one view after another. Every new view bumps a shared generation counter,
+// the worker drops a job between passes as soon as it is no longer the
+// newest, and the viewer ignores frames of older generations. Running passes
+// watch the counter too, the CPU backends before every row and the OpenCL
+// backends between bands of rows (Renderer::render_cancellable), so a pass
+// of an old view stops early instead of finishing for nothing.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::backend::{self, IterationBuffer, RenderParams, Renderer};
+use crate::device::DeviceFilter;
+use crate::supersample::Sampling;
+use crate::viewport::Viewport;
+
+// pixel steps of the progressive passes, each pass is presented when done
+const PASSES: [u32; 4] = [8, 4, 2, 1];
+
+// One finished pass: `buffer` holds `samples` x `samples` samples for every
+// pixel, each pixel covers `step` x `step` screen pixels.
+pub(crate) struct Frame {
+    pub(crate) buffer: IterationBuffer,
+    pub(crate) step: u32,
+    pub(crate) samples: u32,
+    pub(crate) max_iter: i32,
+    // the backend that rendered it
+    pub(crate) backend: &'static str,
+}
+
+enum Message {
+    Render { generation: u64, viewport: Viewport, params: RenderParams, sampling: Sampling },
+    // switch to the named backend, keeping the current one when it fails
+    Backend(&'static str),
+}
+
+pub(crate) struct RenderWorker {
+    messages: Sender<Message>,
+    frames: Receiver<(u64, Frame)>,
+    generation: Arc<AtomicU64>,
+}
+
+impl RenderWorker {
+    // `filter` picks the device when switching backends
+    pub(crate) fn spawn(renderer: Box<dyn Renderer>, filter: DeviceFilter) -> RenderWorker {
+        let (messages, inbox) = mpsc::channel();
+        let (outbox, frames) = mpsc::channel();
+        let generation = Arc::new(AtomicU64::new(0));
+        let latest = Arc::clone(&generation);
+        // never joined, quitting must not wait for a long pass
+        thread::spawn(move || run(renderer, filter, inbox, outbox, latest));
+        RenderWorker { messages, frames, generation }
+    }
+
+    // starts rendering the view, cancelling whatever was rendered before
+    pub(crate) fn render(&self, viewport: &Viewport, params: &RenderParams, sampling: &Sampling) {
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let _ = self.messages.send(Message::Render { generation, viewport: *viewport, params: *params, sampling: *sampling });
+    }
+
+    // takes effect for the next render call
+    pub(crate) fn switch_backend(&self, name: &'static str) {
+        let _ = self.messages.send(Message::Backend(name));
+    }
+
+    // the newest pass of the current view finished since the last call,
+    // waits up to `timeout` for one
+    pub(crate) fn latest(&self, timeout: Duration) -> Option<Frame> {
+        let current = self.generation.load(Ordering::SeqCst);
+        let first = self.frames.recv_timeout(timeout).ok();
+        first.into_iter()
+            .chain(self.frames.try_iter())
+            .filter(|(generation, _)| *generation == current)
+            .map(|(_, frame)| frame)
+            .last()
+    }
+}
+
+fn run(mut renderer: Box<dyn Renderer>, filter: DeviceFilter, inbox: Receiver<Message>, outbox: Sender<(u64, Frame)>,
+       latest: Arc<AtomicU64>) {
+    for message in inbox {
+        let (generation, viewport, params, sampling) = match message {
+            Message::Backend(name) => {
+                switch_renderer(&mut renderer, name, &filter);
+                continue;
+            }
+            Message::Render { generation, viewport, params, sampling } => (generation, viewport, params, sampling),
+        };
+
+        // None is the supersampled pass after the full resolution one
+        let steps = PASSES.iter().map(|&step| Some(step)).chain(sampling.is_enabled().then_some(None));
+        let cancelled = || latest.load(Ordering::SeqCst) != generation;
+        let mut previous: Option<IterationBuffer> = None;
+        for (k, step) in steps.enumerate() {
+            if cancelled() {
+                break;
+            }
+            let frame = match step {
+                // the previous pass covers the same view at twice the step
+                Some(step) => render(renderer.as_mut(), &viewport.coarse(step), &params, previous.as_ref(), &cancelled)
+                    .map(|buffer| Frame { buffer, step, samples: 1, max_iter: params.max_iter, backend: renderer.name() }),
+                None => sampling.viewport(&viewport)
+                    .map_err(|err| println!("supersampling skipped: {}", err)).ok()
+                    .and_then(|fine| render(renderer.as_mut(), &fine, &sampling.params(&params), None, &cancelled))
+                    .map(|buffer| Frame { buffer, step: 1, samples: sampling.samples, max_iter: params.max_iter, backend: renderer.name() }),
+            };
+            let Some(frame) = frame else { break };
+            previous = (k + 1 < PASSES.len()).then(|| frame.buffer.clone());
+            if outbox.send((generation, frame)).is_err() {
+                // the viewer is gone
+                return;
+            }
+        }
+    }
+}
+
+// keeps the current session when the backend is already active
+fn switch_renderer(renderer: &mut Box<dyn Renderer>, name: &str, filter: &DeviceFilter) {
+    if renderer.name() == name {
+        return;
+    }
+    match backend::by_name(name, filter) {
+        Ok(new) => *renderer = new,
+        Err(err) => println!("could not start {} backend: {}", name, err),
+    }
+}
+
+// `coarse` is the previous pass at half the resolution, see
+// Renderer::render_cancellable, None when the pass failed or was cancelled
+fn render(renderer: &mut dyn Renderer, viewport: &Viewport, params: &RenderParams, coarse: Option<&IterationBuffer>,
+          cancelled: &dyn Fn() -> bool) -> Option<IterationBuffer> {
+    let timer = Instant::now();
+    match renderer.render_cancellable(viewport, params, coarse, cancelled) {
+        Ok(None) => None,
+        Ok(Some(buffer)) => {
+            println!("{} took {:.3} ms", renderer.name(), timer.elapsed().as_secs_f64() * 1e3);
+            Some(buffer)
+        }
+        Err(err) => {
+            println!("{} backend failed: {}", renderer.name(), err);
+            None
+        }
+    }
+}